@@ -0,0 +1,157 @@
+//! The Hack instruction set: the names and bits of every computation, destination and jump,
+//! and what a symbol may look like.
+//!
+//! Shared by the translator, which assembles and prints instructions, and `hack_macro`, which
+//! checks and encodes the assembly in the emitters at compile time.
+
+/// The largest value an A-instruction can load
+pub const MAX_A_VALUE: u16 = 0x7FFF;
+
+/// Destination bits of a C-instruction
+pub const DEST_A: u16 = 0b100;
+pub const DEST_D: u16 = 0b010;
+pub const DEST_M: u16 = 0b001;
+
+/// Jump bits of a C-instruction
+pub const JGT: u16 = 0b001;
+pub const JEQ: u16 = 0b010;
+pub const JGE: u16 = 0b011;
+pub const JLT: u16 = 0b100;
+pub const JNE: u16 = 0b101;
+pub const JLE: u16 = 0b110;
+pub const JMP: u16 = 0b111;
+
+/// Every computation by its canonical name. The a-bit is followed by the six c-bits
+const COMPS: &[(&str, u16)] = &[
+    ("0", 0b0_101010),
+    ("1", 0b0_111111),
+    ("-1", 0b0_111010),
+    ("D", 0b0_001100),
+    ("A", 0b0_110000),
+    ("!D", 0b0_001101),
+    ("!A", 0b0_110001),
+    ("-D", 0b0_001111),
+    ("-A", 0b0_110011),
+    ("D+1", 0b0_011111),
+    ("A+1", 0b0_110111),
+    ("D-1", 0b0_001110),
+    ("A-1", 0b0_110010),
+    ("D+A", 0b0_000010),
+    ("D-A", 0b0_010011),
+    ("A-D", 0b0_000111),
+    ("D&A", 0b0_000000),
+    ("D|A", 0b0_010101),
+    ("M", 0b1_110000),
+    ("!M", 0b1_110001),
+    ("-M", 0b1_110011),
+    ("M+1", 0b1_110111),
+    ("M-1", 0b1_110010),
+    ("D+M", 0b1_000010),
+    ("D-M", 0b1_010011),
+    ("M-D", 0b1_000111),
+    ("D&M", 0b1_000000),
+    ("D|M", 0b1_010101),
+    // the commutative operations with their operands swapped
+    ("A+D", 0b0_000010),
+    ("A&D", 0b0_000000),
+    ("A|D", 0b0_010101),
+    ("M+D", 0b1_000010),
+    ("M&D", 0b1_000000),
+    ("M|D", 0b1_010101),
+];
+
+/// Jump mnemonics, indexed by their bits
+const JUMPS: [&str; 8] = ["", "JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+/// Returns the a-bit and the six c-bits of a computation.
+/// The commutative operations are also accepted with their operands swapped, e.g. `A+D`.
+/// A const fn, so the bits of a computation can be named in a constant
+pub const fn comp_bits(comp: &str) -> Option<u16> {
+    let mut i = 0;
+    while i < COMPS.len() {
+        let (name, bits) = COMPS[i];
+        if same_text(name, comp) {
+            return Some(bits);
+        }
+        i += 1;
+    }
+
+    None
+}
+
+// `==` for strings, which is not available in a const fn
+const fn same_text(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+/// The canonical name of a computation
+pub fn comp_name(bits: u16) -> Option<&'static str> {
+    COMPS.iter().find(|(_, b)| *b == bits).map(|(name, _)| *name)
+}
+
+/// The bits of a destination like `AM`. Each register may only be named once
+pub fn dest_bits(dest: &str) -> Option<u16> {
+    let mut bits = 0;
+    for c in dest.chars() {
+        let bit = match c {
+            'A' => DEST_A,
+            'D' => DEST_D,
+            'M' => DEST_M,
+            _ => return None,
+        };
+        if bits & bit != 0 {
+            return None;
+        }
+        bits |= bit;
+    }
+
+    Some(bits)
+}
+
+/// The name of a destination in the usual order, e.g. `AM` and `MD`
+pub fn dest_name(bits: u16) -> String {
+    [(DEST_A, 'A'), (DEST_M, 'M'), (DEST_D, 'D')].iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// The bits of a jump mnemonic. The empty string is no jump
+pub fn jump_bits(jump: &str) -> Option<u16> {
+    JUMPS.iter().position(|name| *name == jump).map(|bits| bits as u16)
+}
+
+/// The mnemonic of a jump, empty for no jump
+pub fn jump_name(bits: u16) -> &'static str {
+    JUMPS[bits as usize & 0b111]
+}
+
+/// A symbol may contain letters, digits, `_`, `.`, `$` and `:` but may not start with a digit
+pub fn is_symbol(name: &str) -> bool {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || "_.$:".contains(c);
+
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(valid_char)
+}
+
+/// Split a line of assembly into its code and any comment after `//`
+pub fn split_comment(line: &str) -> (&str, Option<&str>) {
+    match line.split_once("//") {
+        Some((code, comment)) => (code, Some(comment)),
+        None => (line, None),
+    }
+}
@@ -1,65 +1,78 @@
 extern crate proc_macro;
 
-use proc_macro::TokenTree;
-use std::str::FromStr;
-use proc_macro2::{Group, Span};
-use quote::quote;
-use syn::{parse_macro_input, LitStr, Token};
-use log::debug;
-use quote::__private::ext::RepToTokensExt;
+use proc_macro2::{Ident, Literal, Span};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Expr, LitStr, Token};
 use syn::parse::{Parse, ParseStream};
 
+mod validate;
+
+use validate::{Arg, Code, Field, Line, LineParser, Operand, Piece, Template};
+
 type TokenStream1 = proc_macro::TokenStream;
 type TokenStream2 = proc_macro2::token_stream::TokenStream;
 
-type TokenTree1 = proc_macro::TokenTree;
-type TokenTree2 = proc_macro2::TokenTree;
-
 /// Emit hack assembly
 /// - Takes a raw multiline string.
 /// - In each line it removes leading and trailing whitespace
-/// - does not append a newline at the end
-/// - calls self.emitln with the generated string
+/// - parses every line as an instruction when the macro is expanded
+/// - passes each instruction and its comment to `self.emit`
 #[proc_macro]
 pub fn emit_hack(input: TokenStream1) -> TokenStream1 {
-    // dbg!(&input);
     let input = parse_macro_input!(input as LitStr);
 
-    let literal = trim_hack_str(input);
-
-    let stream = quote! {
-        self.emitln(#literal);
+    let stream = match parse_hack_str(&input, false) {
+        Ok(lines) => emit_lines(&lines, &FormatArgs::default()),
+        Err(error) => Err(error),
     };
 
-    // println!("stream = '{}'", &stream);
-
-    stream.into()
+    stream.unwrap_or_else(|error| error.to_compile_error()).into()
 }
 
-fn trim_hack_str(input: LitStr) -> LitStr {
-    let input = input.value();
-    let mut trimmed = Vec::with_capacity(input.lines().count());
-    let lines = input.lines();
-    for line in lines {
+/// Trims every line and checks it is Hack assembly, failing with an error that names the line.
+/// With `placeholders`, the literal is a format string and its `{...}` may stand in for an
+/// A operand, part of a label name, a whole comp or a whole jump.
+fn parse_hack_str(input: &LitStr, placeholders: bool) -> syn::Result<Vec<Line>> {
+    let mut parser = LineParser::new(placeholders);
+    let mut lines = Vec::new();
+    // the literal as written, to find each line in
+    let source = input.token().to_string();
+    let mut searched = 0;
+    for (i, line) in input.value().lines().enumerate() {
         let line = line.trim();
-        if line.len() == 0 {
+        if line.is_empty() {
             continue;
         }
-        let mut line = line.to_string();
-        line.push_str("\n");
-        trimmed.push(line);
+        let found = source[searched..].find(line).map(|start| searched + start);
+        if let Some(start) = found {
+            searched = start + line.len();
+        }
+        match parser.parse(line) {
+            Ok(parsed) => lines.push(parsed),
+            Err(message) => {
+                // the message names the line. Only compilers that can point inside a literal
+                // narrow the span to it, on stable it covers the whole literal
+                let span = found
+                    .and_then(|start| input.token().subspan(start..start + line.len()))
+                    .unwrap_or_else(|| input.span());
+                let message = format!("invalid hack assembly on line {} `{}`: {}", i + 1, line, message);
+                return Err(syn::Error::new(span, message));
+            }
+        }
     }
 
-    let all = trimmed.iter()
-        .map(|s| s.chars())
-        .flatten()
-        .collect::<String>();
-
-    let all = all.trim_end();
+    Ok(lines)
+}
 
-    let literal = LitStr::new(all, Span::call_site());
+// every line trimmed, without the blank ones
+fn trim_hack_str(input: &LitStr) -> LitStr {
+    let value = input.value();
+    let lines = value.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
 
-    literal
+    LitStr::new(&lines.join("\n"), Span::call_site())
 }
 
 /// Strip a multiline  &str
@@ -70,47 +83,233 @@ fn trim_hack_str(input: LitStr) -> LitStr {
 #[proc_macro]
 pub fn hack_str(input: TokenStream1) -> TokenStream1 {
     let input = parse_macro_input!(input as LitStr);
-    let input = trim_hack_str(input);
+    if let Err(error) = parse_hack_str(&input, false) {
+        return error.to_compile_error().into();
+    }
 
+    let input = trim_hack_str(&input);
     let stream = quote! {
         #input
     };
 
-    // dbg!(&stream);
-    return stream.into();
+    stream.into()
+}
+
+/// The arguments after the literal of a format macro, as in `format!`
+#[derive(Default)]
+struct FormatArgs {
+    positional: Vec<Expr>,
+    named: Vec<(Ident, Expr)>,
 }
 
 struct HackFmt {
     string_literal: LitStr,
-    rest_of_tokens: TokenStream2,
+    args: FormatArgs,
 }
 
 impl Parse for HackFmt {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let string_literal = input.parse::<LitStr>()?;
-        let rest_of_tokens = input.parse::<TokenStream2>()?;
+        let mut args = FormatArgs::default();
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            if input.peek(syn::Ident) && input.peek2(Token![=]) && !input.peek2(Token![==]) {
+                let name = input.parse::<Ident>()?;
+                input.parse::<Token![=]>()?;
+                args.named.push((name, input.parse::<Expr>()?));
+            } else if args.named.is_empty() {
+                args.positional.push(input.parse::<Expr>()?);
+            } else {
+                return Err(input.error("positional arguments cannot follow named arguments"));
+            }
+        }
 
         Ok(HackFmt {
             string_literal,
-            rest_of_tokens,
+            args,
         })
     }
 }
 
+/// The local each argument is evaluated into, once, before any instruction is emitted
+struct Bindings<'a> {
+    args: &'a FormatArgs,
+    // binding, expression and whether a placeholder used it
+    bound: Vec<(Ident, TokenStream2, bool)>,
+    // variables in scope named by a placeholder, after the arguments in `bound`
+    captured: Vec<String>,
+}
 
-fn construct_hack_fmt(input: TokenStream1) -> TokenStream2 {
-    let input = syn::parse::<HackFmt>(input)
-        .expect("failed to parse hack format args");
-    let fmt = input.string_literal;
-    let rest = input.rest_of_tokens;
+impl<'a> Bindings<'a> {
+    fn new(args: &'a FormatArgs) -> Bindings<'a> {
+        let positional = args.positional.iter().map(|expr| quote!(#expr));
+        let named = args.named.iter().map(|(_, expr)| quote!(#expr));
+        let bound = positional.chain(named)
+            .enumerate()
+            .map(|(i, expr)| (format_ident!("__hack_arg{}", i, span = Span::mixed_site()), expr, false))
+            .collect();
+
+        Bindings { args, bound, captured: Vec::new() }
+    }
+
+    // the local holding a reference to the argument
+    fn get(&mut self, arg: &Arg) -> syn::Result<Ident> {
+        let index = match arg {
+            Arg::Index(index) if *index < self.args.positional.len() => *index,
+            Arg::Index(index) => {
+                let message = format!("invalid reference to positional argument {}", index);
+                return Err(syn::Error::new(Span::call_site(), message));
+            }
+            Arg::Name(name) => {
+                let named = self.args.named.iter().position(|(ident, _)| ident == name);
+                match named {
+                    Some(named) => self.args.positional.len() + named,
+                    None => self.capture(name),
+                }
+            }
+        };
+
+        self.bound[index].2 = true;
+        Ok(self.bound[index].0.clone())
+    }
+
+    fn capture(&mut self, name: &str) -> usize {
+        let first = self.args.positional.len() + self.args.named.len();
+        if let Some(i) = self.captured.iter().position(|captured| captured == name) {
+            return first + i;
+        }
+
+        let ident = Ident::new(name, Span::call_site());
+        let binding = format_ident!("__hack_arg{}", self.bound.len(), span = Span::mixed_site());
+        self.bound.push((binding, quote!(#ident), false));
+        self.captured.push(name.to_string());
+        self.bound.len() - 1
+    }
 
-    let fmt = trim_hack_str(fmt);
+    // a `String` of the template's text, formatting in its arguments
+    fn string(&mut self, template: &Template) -> syn::Result<TokenStream2> {
+        let mut format = String::new();
+        let mut values = Vec::new();
+        for piece in &template.0 {
+            match piece {
+                Piece::Text(text) => format.push_str(&text.replace('{', "{{").replace('}', "}}")),
+                Piece::Arg(arg) => {
+                    format.push_str("{}");
+                    values.push(self.get(arg)?);
+                }
+            }
+        }
+
+        if values.is_empty() {
+            let text = template.0.iter()
+                .map(|piece| match piece {
+                    Piece::Text(text) => text.as_str(),
+                    Piece::Arg(_) => "",
+                })
+                .collect::<String>();
+            return Ok(quote!(::std::string::String::from(#text)));
+        }
+
+        Ok(quote!(::std::format!(#format, #(#values),*)))
+    }
+
+    // the bits of a C-instruction field, as a `u16`
+    fn field(&mut self, field: &Field) -> syn::Result<TokenStream2> {
+        match field {
+            Field::Bits(bits) => {
+                let bits = Literal::u16_unsuffixed(*bits);
+                Ok(quote!(#bits))
+            }
+            Field::Arg(arg) => {
+                let binding = self.get(arg)?;
+                Ok(quote!(*#binding))
+            }
+        }
+    }
+}
+
+/// A block that passes each line to `self.emit` as a typed instruction. The arguments are
+/// evaluated once, in order, before the first line like `format!` does
+fn emit_lines(lines: &[Line], args: &FormatArgs) -> syn::Result<TokenStream2> {
+    let path = quote!(crate::hack::instruction);
+    let mut bindings = Bindings::new(args);
+    let mut statements = Vec::with_capacity(lines.len());
+    for line in lines {
+        let instruction = match &line.code {
+            Code::A(Operand::Value(value)) => {
+                let value = Literal::u16_unsuffixed(*value);
+                quote!(#path::Instruction::A(#path::Operand::Value(#value)))
+            }
+            Code::A(Operand::Symbol(template)) => {
+                let symbol = bindings.string(template)?;
+                quote!(#path::Instruction::A(#path::Operand::Symbol(#symbol)))
+            }
+            Code::A(Operand::Arg(arg)) => {
+                let binding = bindings.get(arg)?;
+                quote!(#path::Instruction::A(#path::ToOperand::to_operand(#binding)))
+            }
+            Code::C { dest, comp, jump } => {
+                let dest = Literal::u16_unsuffixed(*dest);
+                let comp = bindings.field(comp)?;
+                let jump = bindings.field(jump)?;
+                quote!(#path::Instruction::C { dest: #dest, comp: #comp, jump: #jump })
+            }
+            Code::Label(template) => {
+                let name = bindings.string(template)?;
+                quote!(#path::Instruction::Label(#name))
+            }
+            Code::Comment(template) => {
+                let text = bindings.string(template)?;
+                quote!(#path::Instruction::Comment(#text))
+            }
+        };
+
+        let note = match &line.note {
+            Some(note) => {
+                let note = bindings.string(note)?;
+                quote!(::std::option::Option::Some(#note))
+            }
+            None => quote!(::std::option::Option::None),
+        };
+
+        statements.push(quote!(self.emit(#instruction, #note);));
+    }
+
+    if let Some((i, _)) = bindings.bound.iter().enumerate().find(|(_, (_, _, used))| !used) {
+        let span = match args.positional.get(i) {
+            Some(expr) => syn::spanned::Spanned::span(expr),
+            None => args.named[i - args.positional.len()].0.span(),
+        };
+        return Err(syn::Error::new(span, "argument never used"));
+    }
+
+    let names = bindings.bound.iter().map(|(name, _, _)| name);
+    let values = bindings.bound.iter().map(|(_, value, _)| value);
+    Ok(quote! {
+        {
+            #(let #names = &(#values);)*
+            #(#statements)*
+        }
+    })
+}
 
+fn construct_hack_fmt(input: TokenStream1) -> syn::Result<TokenStream2> {
+    let input = syn::parse::<HackFmt>(input)?;
+    let fmt = input.string_literal;
+    parse_hack_str(&fmt, true)?;
+
+    let fmt = trim_hack_str(&fmt);
+    let positional = &input.args.positional;
+    let named = input.args.named.iter().map(|(name, expr)| quote!(#name = #expr));
     let stream = quote! {
-        format!("{0}",format_args!{#fmt #rest})
+        ::std::format!(#fmt, #(#positional,)* #(#named),*)
     };
 
-    stream.into()
+    Ok(stream)
 }
 
 /// like format!, except the first argument is stripped of whitespace
@@ -119,27 +318,28 @@ fn construct_hack_fmt(input: TokenStream1) -> TokenStream2 {
 /// - calls to format with the &str and any other args passed
 #[proc_macro]
 pub fn fmt_hack(input: TokenStream1) -> TokenStream1 {
-    let stream = construct_hack_fmt(input);
-
-    // println!("{}",&stream);
+    let stream = match construct_hack_fmt(input) {
+        Ok(stream) => stream,
+        Err(error) => return error.to_compile_error().into(),
+    };
 
-    return stream.into();
+    stream.into()
 }
 
-/// Similar to format!, except the result is passed to self.emitln(&str)
+/// Similar to format!, except each line becomes a typed instruction passed to `self.emit`
 /// - In each line it removes leading and trailing whitespace
-/// - does not append a newline at the end
-/// - calls to format! with the &str and any other args passed
-/// - passes the result to `self.emitln` function
+/// - parses every line as an instruction when the macro is expanded
+/// - `{...}` may stand in for an A operand, part of a label name, a whole comp or a whole
+///   jump, and take any other args passed or name a variable in scope
+/// - a whole A operand is converted with `ToOperand`, a whole comp or jump must be its bits
 #[proc_macro]
 pub fn emit_fmt_hack(input: TokenStream1) -> TokenStream1 {
-    let stream = construct_hack_fmt(input);
+    let input = parse_macro_input!(input as HackFmt);
 
-    let stream = quote! {
-        self.emitln(& #stream);
+    let stream = match parse_hack_str(&input.string_literal, true) {
+        Ok(lines) => emit_lines(&lines, &input.args),
+        Err(error) => Err(error),
     };
 
-    // println!("{}", stream);
-
-    stream.into()
-}
\ No newline at end of file
+    stream.unwrap_or_else(|error| error.to_compile_error()).into()
+}
@@ -0,0 +1,265 @@
+//! Parses the lines of a macro's literal as Hack assembly, so a typo fails the build instead
+//! of the emulator, and so the emitters can be handed typed instructions. The instruction set
+//! comes from `hack_isa`, which the translator's `hack::instruction` module also uses.
+
+use hack_isa::{comp_bits, dest_bits, is_symbol, jump_bits, split_comment, MAX_A_VALUE};
+
+/// What a placeholder takes its value from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Arg {
+    /// `{}` or `{0}`
+    Index(usize),
+    /// `{name}`, a named argument or a variable in scope
+    Name(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Piece {
+    Text(String),
+    Arg(Arg),
+}
+
+/// Text that may contain placeholders
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Template(pub Vec<Piece>);
+
+impl Template {
+    /// The argument, if the template is a single placeholder and nothing else
+    pub fn whole_arg(&self) -> Option<&Arg> {
+        match self.0.as_slice() {
+            [Piece::Arg(arg)] => Some(arg),
+            _ => None,
+        }
+    }
+
+    pub fn has_args(&self) -> bool {
+        self.0.iter().any(|piece| matches!(piece, Piece::Arg(_)))
+    }
+
+    // the text with each placeholder replaced by a letter, so what is around it can be
+    // checked as a symbol
+    fn filled(&self) -> String {
+        self.0.iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => text.as_str(),
+                Piece::Arg(_) => "x",
+            })
+            .collect()
+    }
+}
+
+/// A C-instruction field, known now or given by an argument
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    Bits(u16),
+    Arg(Arg),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Value(u16),
+    Symbol(Template),
+    /// A constant or symbol given by an argument as a whole
+    Arg(Arg),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Code {
+    A(Operand),
+    C { dest: u16, comp: Field, jump: Field },
+    Label(Template),
+    /// A line with nothing but a comment
+    Comment(Template),
+}
+
+/// One line of assembly and the comment after it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub code: Code,
+    pub note: Option<Template>,
+}
+
+/// Parses the lines of one literal. With `placeholders`, the literal is a format string and
+/// `{...}` may stand in for an A operand, part of a label name, a whole comp or a whole jump.
+pub struct LineParser {
+    placeholders: bool,
+    // the argument of the next `{}`, which counts on across lines like in `format!`
+    next_index: usize,
+}
+
+impl LineParser {
+    pub fn new(placeholders: bool) -> LineParser {
+        LineParser { placeholders, next_index: 0 }
+    }
+
+    /// Parse one trimmed line that is not empty
+    pub fn parse(&mut self, line: &str) -> Result<Line, String> {
+        let (code, note) = split_comment(line);
+        let code = code.trim();
+
+        if code.is_empty() {
+            let text = self.note(note.unwrap_or_default())?;
+            return Ok(Line { code: Code::Comment(text), note: None });
+        }
+
+        if !self.placeholders && code.contains(['{', '}']) {
+            return Err(String::from("braces are only allowed in the format macros"));
+        }
+
+        let code = if let Some(operand) = code.strip_prefix('@') {
+            Code::A(self.a_operand(operand)?)
+        } else if let Some(label) = code.strip_prefix('(') {
+            let name = label.strip_suffix(')')
+                .ok_or_else(|| String::from("unterminated label"))?;
+            let template = self.template(name)?;
+            if !is_symbol(&template.filled()) {
+                return Err(format!("invalid label name '{}'", name));
+            }
+            Code::Label(template)
+        } else {
+            self.c_instruction(code)?
+        };
+
+        let note = note.map(|note| self.note(note)).transpose()?;
+        Ok(Line { code, note })
+    }
+
+    fn a_operand(&mut self, operand: &str) -> Result<Operand, String> {
+        let template = self.template(operand)?;
+        if let Some(arg) = template.whole_arg() {
+            return Ok(Operand::Arg(arg.clone()));
+        }
+
+        if operand.starts_with(|c: char| c.is_ascii_digit()) {
+            return match operand.parse::<u16>() {
+                Ok(value) if value <= MAX_A_VALUE => Ok(Operand::Value(value)),
+                _ => Err(format!("invalid constant '{}'", operand)),
+            };
+        }
+
+        if !is_symbol(&template.filled()) {
+            return Err(format!("invalid symbol '{}'", operand));
+        }
+
+        Ok(Operand::Symbol(template))
+    }
+
+    fn c_instruction(&mut self, code: &str) -> Result<Code, String> {
+        let (dest, rest) = match code.split_once('=') {
+            Some((dest, rest)) => (dest.trim(), rest),
+            None => ("", code),
+        };
+        let (comp, jump) = match rest.split_once(';') {
+            Some((comp, jump)) => (comp.trim(), Some(jump.trim())),
+            None => (rest.trim(), None),
+        };
+
+        if code.contains('=') && dest.is_empty() {
+            return Err(String::from("missing destination before '='"));
+        }
+        if dest.contains(['{', '}']) {
+            return Err(format!("placeholder '{}' can not stand in for a destination", dest));
+        }
+        let dest = dest_bits(dest).ok_or_else(|| format!("invalid destination '{}'", dest))?;
+
+        let comp = match self.field(comp)? {
+            Some(field) => field,
+            None => Field::Bits(comp_bits(comp).ok_or_else(|| format!("invalid computation '{}'", comp))?),
+        };
+
+        let jump = match jump {
+            Some(jump) => match self.field(jump)? {
+                Some(field) => field,
+                None => jump_bits(jump)
+                    .filter(|bits| *bits != 0)
+                    .map(Field::Bits)
+                    .ok_or_else(|| format!("invalid jump '{}'", jump))?,
+            },
+            None => Field::Bits(0),
+        };
+
+        Ok(Code::C { dest, comp, jump })
+    }
+
+    // a comp or jump given by an argument, which has to be the whole field
+    fn field(&mut self, text: &str) -> Result<Option<Field>, String> {
+        let template = self.template(text)?;
+        if let Some(arg) = template.whole_arg() {
+            return Ok(Some(Field::Arg(arg.clone())));
+        }
+        if template.has_args() {
+            return Err(format!("a placeholder has to be the whole of '{}'", text));
+        }
+
+        Ok(None)
+    }
+
+    // a comment is kept as written, so only the format macros look for placeholders in it
+    fn note(&mut self, text: &str) -> Result<Template, String> {
+        if !self.placeholders {
+            return Ok(Template(vec![Piece::Text(text.to_string())]));
+        }
+
+        self.template(text)
+    }
+
+    fn template(&mut self, text: &str) -> Result<Template, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => return Err(format!("unbalanced braces in '{}'", text)),
+                            Some(c) => name.push(c),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Piece::Arg(self.arg(&name)?));
+                }
+                '}' => return Err(format!("unbalanced braces in '{}'", text)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Text(literal));
+        }
+
+        Ok(Template(pieces))
+    }
+
+    // the argument named inside `{...}`
+    fn arg(&mut self, name: &str) -> Result<Arg, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            self.next_index += 1;
+            return Ok(Arg::Index(self.next_index - 1));
+        }
+
+        if let Ok(index) = name.parse::<usize>() {
+            return Ok(Arg::Index(index));
+        }
+
+        let is_ident = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !is_ident {
+            return Err(format!("placeholder '{{{}}}' has to be empty, a number or a name", name));
+        }
+
+        Ok(Arg::Name(name.to_string()))
+    }
+}
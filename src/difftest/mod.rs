@@ -0,0 +1,313 @@
+//! Differential testing of the emitters.
+//! A program is executed by the reference VM interpreter and, in lockstep, by the Hack emulator
+//! running each emitter's translation. After every VM command the machine states are compared.
+//! With the peephole pass on, code moves between commands, so the states are only compared once
+//! the program has halted.
+
+use std::path::{Path, PathBuf};
+
+use crate::hack::assembler::{self, AssembledProgram};
+use crate::hack::emulator::{Emulator, StopReason as EmulatorStop};
+use crate::transformer::{Backend, EmitOptions, Lowering};
+use crate::vm::interpreter::{Interpreter, StopReason, VmError};
+use crate::vm::VmFile;
+
+/// Most instructions the emulator may execute to get from one VM command to the next
+const MAX_CYCLES_PER_COMMAND: u64 = 100_000;
+
+/// Segment pointers used when a program is run without bootstrap code, as in the project 7 tests
+const DEFAULT_POINTERS: [(u16, i16); 5] = [(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)];
+
+const STACK_BASE: u16 = 256;
+const HEAP_BASE: u16 = 2048;
+
+pub struct DiffOptions {
+    pub emit_init: bool,
+    /// Most VM commands to execute
+    pub max_steps: u64,
+    /// The emitters to check
+    pub backends: Vec<Backend>,
+    /// How the emitters lower commands
+    pub lowering: Lowering,
+    /// Clean up the emitted instructions, and compare the machines only once the program halts
+    pub peephole: bool,
+    /// Where to write the translation of each backend as `name.backend.asm`, for inspection.
+    /// Nothing is written if None
+    pub keep: Option<PathBuf>,
+}
+
+/// Where a translation first stopped behaving like the interpreter
+pub struct Divergence {
+    pub backend: Backend,
+    /// The VM command that was just executed, as `File.vm: source`. None before the first command
+    pub command: Option<String>,
+    /// The range of ROM addresses translated from that command
+    pub rom_range: Option<(usize, usize)>,
+    /// Whether the states were only compared once the program halted
+    pub halted: bool,
+    pub differences: Vec<String>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.command {
+            Some(command) if self.halted => {
+                writeln!(f, "[{}] final states diverge, the program halted after '{}'", self.backend, command)?
+            }
+            Some(command) => writeln!(f, "[{}] states diverge after '{}'", self.backend, command)?,
+            None => writeln!(f, "[{}] states diverge before the first command", self.backend)?,
+        }
+        if let Some((start, end)) = self.rom_range {
+            writeln!(f, "    translated to ROM[{}..{}]", start, end)?;
+        }
+        for difference in self.differences.iter() {
+            writeln!(f, "    {}", difference)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub enum DiffError {
+    Vm(VmError),
+    /// The translation failed to translate or assemble
+    Translate(String),
+    /// The program did not halt, so there is no final state to compare
+    Unfinished(String),
+}
+
+impl std::fmt::Display for DiffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DiffError::Vm(error) => write!(f, "{}", error),
+            DiffError::Translate(msg) | DiffError::Unfinished(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<VmError> for DiffError {
+    fn from(error: VmError) -> Self {
+        DiffError::Vm(error)
+    }
+}
+
+/// Check the chosen emitters against the interpreter, returning the outcome for each backend
+pub fn check_all(files: &[VmFile], options: &DiffOptions) -> Result<Vec<(Backend, Option<Divergence>)>, DiffError> {
+    options.backends.iter()
+        .map(|&backend| Ok((backend, check_backend(backend, files, options)?)))
+        .collect()
+}
+
+/// Check one emitter against the interpreter, returning the first divergence if there is one
+pub fn check_backend(backend: Backend, files: &[VmFile], options: &DiffOptions) -> Result<Option<Divergence>, DiffError> {
+    let (source, addresses) = translate(backend, files, options)?;
+    if let Some(out_path) = &options.keep {
+        let asm_path = backend_path(out_path, backend);
+        std::fs::write(&asm_path, &source)
+            .map_err(|e| DiffError::Translate(format!("failed to write '{}': {}", asm_path.display(), e)))?;
+    }
+    let program = assembler::assemble(&source)
+        .map_err(|e| DiffError::Translate(format!("[{}] {}", backend, e)))?;
+
+    // the address the emulator reaches when the interpreter is about to execute command `pc`
+    let address_of = |pc: usize| addresses.get(pc).copied().unwrap_or(program.rom.len());
+    let sources: Vec<String> = files.iter()
+        .flat_map(|file| file.commands.iter().map(move |(_, source)| format!("{}.vm: {}", file.name, source)))
+        .collect();
+
+    let mut interpreter = Interpreter::new(files)?;
+    let mut emulator = Emulator::new(program.rom.clone());
+    if options.emit_init {
+        interpreter.bootstrap()?;
+    } else {
+        for (address, value) in DEFAULT_POINTERS {
+            interpreter.poke(address, value);
+            emulator.poke(address, value);
+        }
+    }
+
+    if options.peephole {
+        return check_halted(backend, interpreter, emulator, &program, &sources, options);
+    }
+
+    let divergence = |command: Option<usize>, differences: Vec<String>| Divergence {
+        backend,
+        command: command.map(|pc| sources[pc].clone()),
+        rom_range: command.map(|pc| (address_of(pc), address_of(pc + 1))),
+        halted: false,
+        differences,
+    };
+
+    // run any bootstrap and prelude code
+    if !run_to(&mut emulator, address_of(interpreter.pc()), false) {
+        let msg = format!("emulator never reached ROM[{}]", address_of(interpreter.pc()));
+        return Ok(Some(divergence(None, vec![msg])));
+    }
+    let differences = compare(&interpreter, &emulator, &program);
+    if !differences.is_empty() {
+        return Ok(Some(divergence(None, differences)));
+    }
+
+    for _ in 0..options.max_steps {
+        let pc = interpreter.pc();
+        let stop = interpreter.step()?;
+
+        // once the interpreter stops the emulator has nowhere to sync to, so just compare
+        if stop.is_none() {
+            let command_size = address_of(pc + 1).saturating_sub(address_of(pc));
+            if !run_to(&mut emulator, address_of(interpreter.pc()), command_size > 0) {
+                let msg = format!("emulator never reached ROM[{}]", address_of(interpreter.pc()));
+                return Ok(Some(divergence(Some(pc), vec![msg])));
+            }
+        } else {
+            run_to(&mut emulator, address_of(pc + 1), address_of(pc + 1) > address_of(pc));
+        }
+
+        let differences = compare(&interpreter, &emulator, &program);
+        if !differences.is_empty() {
+            return Ok(Some(divergence(Some(pc), differences)));
+        }
+
+        if matches!(stop, Some(StopReason::Halted | StopReason::EndOfProgram | StopReason::Returned)) {
+            break;
+        }
+    }
+
+    Ok(None)
+}
+
+// run both machines until the program halts, then compare them once
+fn check_halted(
+    backend: Backend,
+    mut interpreter: Interpreter,
+    mut emulator: Emulator,
+    program: &AssembledProgram,
+    sources: &[String],
+    options: &DiffOptions,
+) -> Result<Option<Divergence>, DiffError> {
+    let mut last = None;
+    let mut stop = StopReason::StepLimit;
+    for _ in 0..options.max_steps {
+        let pc = interpreter.pc();
+        let reason = interpreter.step()?;
+        // running past the end executes nothing
+        if reason != Some(StopReason::EndOfProgram) {
+            last = Some(pc);
+        }
+        if let Some(reason) = reason {
+            stop = reason;
+            break;
+        }
+    }
+
+    match stop {
+        StopReason::Halted | StopReason::EndOfProgram => {}
+        StopReason::StepLimit => {
+            let msg = format!("the program did not halt within {} commands", options.max_steps);
+            return Err(DiffError::Unfinished(msg));
+        }
+        StopReason::Returned => {
+            let msg = "the outermost function returned with no caller, so the program has no final state".to_string();
+            return Err(DiffError::Unfinished(msg));
+        }
+    }
+
+    let max_cycles = (interpreter.steps() + 1) * MAX_CYCLES_PER_COMMAND;
+    let differences = match emulator.run(max_cycles, None) {
+        EmulatorStop::CycleLimit => vec![format!("emulator did not halt within {} cycles", max_cycles)],
+        EmulatorStop::Halted | EmulatorStop::EndOfProgram => compare(&interpreter, &emulator, program),
+    };
+
+    if differences.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Divergence {
+        backend,
+        command: last.map(|pc| sources[pc].clone()),
+        rom_range: None,
+        halted: true,
+        differences,
+    }))
+}
+
+// `out/Prog.asm` -> `out/Prog.simple.asm`
+fn backend_path(out_path: &Path, backend: Backend) -> PathBuf {
+    let stem = out_path.file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+    out_path.with_file_name(format!("{}.{}.asm", stem, backend))
+}
+
+// translate with a specific emitter, returning the assembly and the ROM address of every command
+fn translate(backend: Backend, files: &[VmFile], options: &DiffOptions) -> Result<(String, Vec<usize>), DiffError> {
+    let mut out = Vec::new();
+    let emit_options = EmitOptions { lowering: options.lowering, peephole: options.peephole };
+    let mut context = backend.context_with(emit_options);
+    for file in files.iter() {
+        context.write_file(&file.commands, &mut out, options.emit_init, &file.name)
+            .map_err(|e| DiffError::Translate(e.to_string()))?;
+    }
+
+    let source = String::from_utf8(out).expect("Emitted assembly is always valid utf-8");
+    Ok((source, context.command_addresses().to_vec()))
+}
+
+// run until PC reaches the address. Returns false if the cycle budget ran out first
+fn run_to(emulator: &mut Emulator, address: usize, step_first: bool) -> bool {
+    if step_first && !emulator.step() {
+        return emulator.pc() as usize == address;
+    }
+
+    for _ in 0..MAX_CYCLES_PER_COMMAND {
+        if emulator.pc() as usize == address {
+            return true;
+        }
+        if !emulator.step() {
+            return emulator.pc() as usize == address;
+        }
+    }
+
+    false
+}
+
+// describe every observable difference between the two machines
+fn compare(interpreter: &Interpreter, emulator: &Emulator, program: &AssembledProgram) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut check = |name: String, expected: i16, actual: i16| {
+        if expected != actual {
+            differences.push(format!("{}: expected {}, found {}", name, expected, actual));
+        }
+    };
+
+    // segment pointers and temp
+    let names = ["SP", "LCL", "ARG", "THIS", "THAT"];
+    for (address, name) in names.iter().enumerate() {
+        check(name.to_string(), interpreter.peek(address as u16), emulator.peek(address as u16));
+    }
+    for address in 5..13 {
+        check(format!("RAM[{}] (temp {})", address, address - 5), interpreter.peek(address), emulator.peek(address));
+    }
+
+    // the live stack, minus return addresses which can never match
+    let return_slots: Vec<u16> = interpreter.return_address_slots().collect();
+    let sp = interpreter.peek(0) as u16;
+    for address in STACK_BASE..sp.max(STACK_BASE) {
+        if !return_slots.contains(&address) {
+            check(format!("RAM[{}] (stack)", address), interpreter.peek(address), emulator.peek(address));
+        }
+    }
+
+    // memory written outside of the stack
+    for address in interpreter.written().range(HEAP_BASE..) {
+        check(format!("RAM[{}]", address), interpreter.peek(*address), emulator.peek(*address));
+    }
+
+    let mut missing = Vec::new();
+    for (symbol, value) in interpreter.statics() {
+        match program.symbols.get(symbol) {
+            Some(address) => check(format!("{} (RAM[{}])", symbol, address), *value, emulator.peek(*address)),
+            None => missing.push(format!("{}: static is missing from the assembly", symbol)),
+        }
+    }
+
+    differences.extend(missing);
+    differences
+}
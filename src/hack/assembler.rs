@@ -0,0 +1,115 @@
+//! Assembles hack assembly text into Hack machine code
+
+use std::collections::HashMap;
+
+use super::instruction::{split_comment, Instruction, MAX_A_VALUE};
+
+/// The first RAM address handed out to variables
+const FIRST_VARIABLE_ADDRESS: u16 = 16;
+
+#[derive(Clone, Debug)]
+pub struct AssemblerError {
+    /// 1-based line number in the assembly source
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Assembler Error: {} on line {}", self.message, self.line)
+    }
+}
+
+/// Machine code and the symbol table it was resolved with.
+pub struct AssembledProgram {
+    pub rom: Vec<u16>,
+    pub symbols: HashMap<String, u16>,
+}
+
+impl AssembledProgram {
+    /// The `.hack` text format: one instruction per line, written as 16 binary digits
+    pub fn to_hack_text(&self) -> String {
+        let mut text = String::with_capacity(self.rom.len() * 17);
+        for word in self.rom.iter() {
+            text.push_str(&format!("{:016b}\n", word));
+        }
+
+        text
+    }
+}
+
+/// Symbols every hack program can use without declaring them
+fn predefined_symbols() -> HashMap<String, u16> {
+    let mut symbols = HashMap::new();
+    symbols.insert("SP".to_string(), 0);
+    symbols.insert("LCL".to_string(), 1);
+    symbols.insert("ARG".to_string(), 2);
+    symbols.insert("THIS".to_string(), 3);
+    symbols.insert("THAT".to_string(), 4);
+    for i in 0..16 {
+        symbols.insert(format!("R{}", i), i);
+    }
+    symbols.insert("SCREEN".to_string(), 0x4000);
+    symbols.insert("KBD".to_string(), 0x6000);
+
+    symbols
+}
+
+/// Assemble a whole program.
+/// Labels are resolved in a first pass, then variables are allocated from RAM[16] in order of first use.
+pub fn assemble(source: &str) -> Result<AssembledProgram, AssemblerError> {
+    let mut instructions = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let parsed = Instruction::parse(split_comment(line).0).map_err(|message| AssemblerError {
+            line: i + 1,
+            message,
+        })?;
+
+        if let Some(instruction) = parsed {
+            instructions.push((i + 1, instruction));
+        }
+    }
+
+    // first pass: bind labels to the address of the next instruction
+    let mut symbols = predefined_symbols();
+    let mut address: u16 = 0;
+    for (line, instruction) in instructions.iter() {
+        match instruction {
+            Instruction::Label(name) => {
+                if symbols.contains_key(name) {
+                    return Err(AssemblerError {
+                        line: *line,
+                        message: format!("symbol '{}' is declared more than once", name),
+                    });
+                }
+                symbols.insert(name.clone(), address);
+            }
+            _ if instruction.is_code() => {
+                if address > MAX_A_VALUE {
+                    return Err(AssemblerError {
+                        line: *line,
+                        message: "program does not fit in ROM".to_string(),
+                    });
+                }
+                address += 1;
+            }
+            _ => {}
+        }
+    }
+
+    // second pass: allocate variables and encode
+    let mut next_variable = FIRST_VARIABLE_ADDRESS;
+    let mut rom = Vec::with_capacity(address as usize);
+    for (_, instruction) in instructions.iter() {
+        let word = instruction.encode(|name| {
+            *symbols.entry(name.to_string()).or_insert_with(|| {
+                let variable = next_variable;
+                next_variable += 1;
+                variable
+            })
+        });
+        rom.extend(word);
+    }
+
+    Ok(AssembledProgram { rom, symbols })
+}
@@ -0,0 +1,89 @@
+//! Assembly held in memory between being emitted and being written out, so that it can still
+//! be cleaned up by the peephole pass.
+
+use std::io::{self, Write};
+
+use super::instruction::Instruction;
+use super::peephole;
+
+/// One line of emitted assembly
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub instruction: Instruction,
+    /// A comment after the instruction on the same line
+    pub note: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InstructionBuffer {
+    lines: Vec<Line>,
+    instructions: usize,
+}
+
+impl InstructionBuffer {
+    pub fn new() -> InstructionBuffer {
+        InstructionBuffer::default()
+    }
+
+    /// Add an instruction, with any comment to print after it on the same line
+    pub fn push(&mut self, instruction: Instruction, note: Option<String>) {
+        self.instructions += instruction.is_code() as usize;
+        self.lines.push(Line { instruction, note });
+    }
+
+    /// Number of instructions buffered
+    pub fn instruction_count(&self) -> usize {
+        self.instructions
+    }
+
+    /// Write out and clear the buffer, numbering the instructions from `first_address`.
+    /// Returns the number of instructions written.
+    ///
+    /// With `peephole`, instructions that have no effect are left out. Any of `addresses` that
+    /// point into the buffer, i.e. are `first_address` or above, are moved along with the
+    /// instruction they pointed at.
+    pub fn flush<W: Write>(
+        &mut self,
+        out: &mut W,
+        first_address: usize,
+        peephole: bool,
+        addresses: &mut [usize],
+    ) -> io::Result<usize> {
+        let keep = if peephole {
+            peephole::optimize(&self.lines)
+        } else {
+            vec![true; self.lines.len()]
+        };
+
+        // kept_before[i] = number of kept instructions among the first i in the buffer
+        let mut kept_before = vec![0];
+        let mut address = first_address;
+        for (line, keep) in self.lines.iter().zip(keep) {
+            let instruction = &line.instruction;
+            if !instruction.is_code() {
+                writeln!(out, "{}", instruction)?;
+                continue;
+            }
+
+            if keep {
+                let text = match &line.note {
+                    Some(note) => format!("{:12}//{}", instruction.to_string(), note),
+                    None => instruction.to_string(),
+                };
+                writeln!(out, "{:90}//{:3}", text, address)?;
+                address += 1;
+            }
+            kept_before.push(address - first_address);
+        }
+
+        let buffered = kept_before.len() - 1;
+        for address in addresses.iter_mut().filter(|a| **a >= first_address) {
+            let index = (*address - first_address).min(buffered);
+            *address = first_address + kept_before[index];
+        }
+
+        self.lines.clear();
+        self.instructions = 0;
+        Ok(address - first_address)
+    }
+}
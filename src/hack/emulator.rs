@@ -0,0 +1,199 @@
+//! An emulator of the Hack CPU, its instruction ROM and data RAM
+
+/// Number of addressable words of data memory
+pub const RAM_SIZE: usize = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    /// The requested number of cycles were executed
+    CycleLimit,
+    /// The program reached the halt address or jumped to itself forever
+    Halted,
+    /// The program counter left the loaded program
+    EndOfProgram,
+}
+
+pub struct Emulator {
+    rom: Vec<u16>,
+    ram: Vec<i16>,
+    a: i16,
+    d: i16,
+    pc: u16,
+    cycles: u64,
+}
+
+impl Emulator {
+    pub fn new(rom: Vec<u16>) -> Emulator {
+        Emulator {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn a(&self) -> i16 {
+        self.a
+    }
+
+    pub fn d(&self) -> i16 {
+        self.d
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn ram(&self) -> &[i16] {
+        &self.ram
+    }
+
+    pub fn peek(&self, address: u16) -> i16 {
+        self.ram[address as usize % RAM_SIZE]
+    }
+
+    pub fn poke(&mut self, address: u16, value: i16) {
+        self.ram[address as usize % RAM_SIZE] = value;
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Clear the registers and restart the program. RAM is left untouched.
+    pub fn reset(&mut self) {
+        self.a = 0;
+        self.d = 0;
+        self.pc = 0;
+        self.cycles = 0;
+    }
+
+    // the RAM address selected by the A register
+    fn address(&self) -> usize {
+        (self.a as u16 as usize) % RAM_SIZE
+    }
+
+    /// Execute the instruction at PC.
+    /// Returns false without doing anything if PC is outside the program.
+    pub fn step(&mut self) -> bool {
+        let instruction = match self.rom.get(self.pc as usize) {
+            Some(instruction) => *instruction,
+            None => return false,
+        };
+        self.cycles += 1;
+
+        // A-instruction
+        if instruction & 0x8000 == 0 {
+            self.a = instruction as i16;
+            self.pc = self.pc.wrapping_add(1);
+            return true;
+        }
+
+        // C-instruction
+        let uses_m = instruction & 0x1000 != 0;
+        let control = (instruction >> 6) & 0b111111;
+        let dest = (instruction >> 3) & 0b111;
+        let jump = instruction & 0b111;
+
+        let y = if uses_m { self.ram[self.address()] } else { self.a };
+        let out = alu(self.d, y, control);
+
+        // M is written, and a jump taken, with the A from before it is updated
+        let old_a = self.a;
+        if dest & 0b001 != 0 {
+            let address = self.address();
+            self.ram[address] = out;
+        }
+        if dest & 0b100 != 0 {
+            self.a = out;
+        }
+        if dest & 0b010 != 0 {
+            self.d = out;
+        }
+
+        let taken = match jump {
+            0b000 => false,
+            0b001 => out > 0,
+            0b010 => out == 0,
+            0b011 => out >= 0,
+            0b100 => out < 0,
+            0b101 => out != 0,
+            0b110 => out <= 0,
+            _ => true,
+        };
+
+        if taken {
+            self.pc = old_a as u16;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
+
+        true
+    }
+
+    // a program has halted when it jumps unconditionally to the jump itself,
+    // or to the A-instruction which loads the jump's own address. eg. `(L) @L 0;JMP`
+    fn is_halted(&self) -> bool {
+        let pc = self.pc as usize;
+        let is_loop_jump = |at: usize| {
+            self.rom.get(at).is_some_and(|i| i & 0xE007 == 0xE007)
+        };
+
+        if is_loop_jump(pc) && self.a as u16 as usize == pc {
+            return true;
+        }
+
+        pc + 1 < self.rom.len()
+            && self.rom[pc] == pc as u16
+            && is_loop_jump(pc + 1)
+    }
+
+    /// Run for at most `max_cycles` cycles.
+    /// Stops early when PC reaches `halt_address`, the program halts in a tight loop, or PC leaves the program.
+    pub fn run(&mut self, max_cycles: u64, halt_address: Option<u16>) -> StopReason {
+        for _ in 0..max_cycles {
+            if Some(self.pc) == halt_address || self.is_halted() {
+                return StopReason::Halted;
+            }
+
+            if !self.step() {
+                return StopReason::EndOfProgram;
+            }
+        }
+
+        StopReason::CycleLimit
+    }
+}
+
+/// The Hack ALU. `control` holds the six control bits zx nx zy ny f no, most significant first.
+fn alu(x: i16, y: i16, control: u16) -> i16 {
+    let zx = control & 0b100000 != 0;
+    let nx = control & 0b010000 != 0;
+    let zy = control & 0b001000 != 0;
+    let ny = control & 0b000100 != 0;
+    let f = control & 0b000010 != 0;
+    let no = control & 0b000001 != 0;
+
+    let mut x = if zx { 0 } else { x };
+    if nx {
+        x = !x;
+    }
+    let mut y = if zy { 0 } else { y };
+    if ny {
+        y = !y;
+    }
+
+    let out = if f { x.wrapping_add(y) } else { x & y };
+
+    if no {
+        !out
+    } else {
+        out
+    }
+}
@@ -0,0 +1,187 @@
+//! Hack assembly as typed instructions.
+//! Emitters produce them, the peephole pass inspects them, and the assembler encodes them,
+//! so none of them have to look at the text of a line.
+
+pub use hack_isa::{
+    comp_bits, is_symbol, split_comment, DEST_A, DEST_D, DEST_M, JEQ, JGE, JGT, JLE, JLT, JMP,
+    JNE, MAX_A_VALUE,
+};
+use hack_isa::{comp_name, dest_bits, dest_name, jump_bits, jump_name};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Value(u16),
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// `@value` or `@symbol`
+    A(Operand),
+    /// `dest=comp;jump`, as the bits of each field
+    C { dest: u16, comp: u16, jump: u16 },
+    /// `(symbol)`, which names the address of the next instruction
+    Label(String),
+    /// `//text`, for readers of the assembly only
+    Comment(String),
+}
+
+/// What an emitter can load with an A-instruction: a constant or the name of a symbol
+pub trait ToOperand {
+    fn to_operand(&self) -> Operand;
+}
+
+impl ToOperand for str {
+    fn to_operand(&self) -> Operand {
+        Operand::Symbol(self.to_string())
+    }
+}
+
+impl ToOperand for String {
+    fn to_operand(&self) -> Operand {
+        self.as_str().to_operand()
+    }
+}
+
+impl<T: ToOperand + ?Sized> ToOperand for &T {
+    fn to_operand(&self) -> Operand {
+        (**self).to_operand()
+    }
+}
+
+// emitters only ever load constants they have checked are in range, so one that is not is a bug
+macro_rules! constant_operand {
+    ($($t:ty),*) => {$(
+        impl ToOperand for $t {
+            fn to_operand(&self) -> Operand {
+                match u16::try_from(*self) {
+                    Ok(value) if value <= MAX_A_VALUE => Operand::Value(value),
+                    _ => panic!("{} does not fit in an A-instruction", self),
+                }
+            }
+        }
+    )*};
+}
+
+constant_operand!(i16, i32, usize);
+
+/// The bits of a computation named in a constant. An invalid name fails the build
+pub const fn comp(name: &str) -> u16 {
+    match comp_bits(name) {
+        Some(bits) => bits,
+        None => panic!("invalid computation"),
+    }
+}
+
+impl Instruction {
+    /// Parse the code of one line, without any comment. Returns None for an empty line
+    pub fn parse(code: &str) -> Result<Option<Instruction>, String> {
+        let code = code.trim();
+
+        if code.is_empty() {
+            return Ok(None);
+        }
+
+        if let Some(operand) = code.strip_prefix('@') {
+            return parse_a_operand(operand).map(|op| Some(Instruction::A(op)));
+        }
+
+        if let Some(label) = code.strip_prefix('(') {
+            let name = label
+                .strip_suffix(')')
+                .ok_or_else(|| format!("unterminated label '{}'", code))?;
+            if !is_symbol(name) {
+                return Err(format!("invalid label name '{}'", name));
+            }
+            return Ok(Some(Instruction::Label(name.to_string())));
+        }
+
+        parse_c_instruction(code).map(Some)
+    }
+
+    /// Whether the instruction takes a word of ROM. Labels and comments take none
+    pub fn is_code(&self) -> bool {
+        matches!(self, Instruction::A(_) | Instruction::C { .. })
+    }
+
+    /// The machine code of the instruction, using `resolve` for the value of any symbol.
+    /// None for labels and comments
+    pub fn encode(&self, mut resolve: impl FnMut(&str) -> u16) -> Option<u16> {
+        match self {
+            Instruction::A(Operand::Value(value)) => Some(*value),
+            Instruction::A(Operand::Symbol(name)) => Some(resolve(name)),
+            Instruction::C { dest, comp, jump } => Some(0b111 << 13 | comp << 6 | dest << 3 | jump),
+            Instruction::Label(_) | Instruction::Comment(_) => None,
+        }
+    }
+
+    /// Whether a C-instruction stores its result in any of the `DEST_*` registers given
+    pub fn writes(&self, registers: u16) -> bool {
+        matches!(self, Instruction::C { dest, .. } if dest & registers != 0)
+    }
+
+    /// Whether a C-instruction computes with the D register
+    pub fn reads_d(&self) -> bool {
+        matches!(self, Instruction::C { comp, .. } if comp_name(*comp).is_some_and(|name| name.contains('D')))
+    }
+
+    /// Whether a C-instruction may jump
+    pub fn jumps(&self) -> bool {
+        matches!(self, Instruction::C { jump, .. } if *jump != 0)
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Instruction::A(Operand::Value(value)) => write!(f, "@{}", value),
+            Instruction::A(Operand::Symbol(name)) => write!(f, "@{}", name),
+            Instruction::C { dest, comp, jump } => {
+                if *dest != 0 {
+                    write!(f, "{}=", dest_name(*dest))?;
+                }
+                write!(f, "{}", comp_name(*comp).unwrap_or("?"))?;
+                if *jump != 0 {
+                    write!(f, ";{}", jump_name(*jump))?;
+                }
+                Ok(())
+            }
+            Instruction::Label(name) => write!(f, "({})", name),
+            Instruction::Comment(text) => write!(f, "//{}", text),
+        }
+    }
+}
+
+fn parse_a_operand(operand: &str) -> Result<Operand, String> {
+    if operand.starts_with(|c: char| c.is_ascii_digit()) {
+        let value = operand
+            .parse::<u16>()
+            .ok()
+            .filter(|v| *v <= MAX_A_VALUE)
+            .ok_or_else(|| format!("invalid constant '{}'", operand))?;
+        return Ok(Operand::Value(value));
+    }
+
+    if !is_symbol(operand) {
+        return Err(format!("invalid symbol '{}'", operand));
+    }
+
+    Ok(Operand::Symbol(operand.to_string()))
+}
+
+fn parse_c_instruction(line: &str) -> Result<Instruction, String> {
+    let (dest, rest) = match line.split_once('=') {
+        Some((dest, rest)) => (dest.trim(), rest),
+        None => ("", line),
+    };
+    let (comp, jump) = match rest.split_once(';') {
+        Some((comp, jump)) => (comp.trim(), jump.trim()),
+        None => (rest.trim(), ""),
+    };
+
+    let dest = dest_bits(dest).ok_or_else(|| format!("invalid destination '{}'", dest))?;
+    let comp = comp_bits(comp).ok_or_else(|| format!("invalid computation '{}'", comp))?;
+    let jump = jump_bits(jump).ok_or_else(|| format!("invalid jump '{}'", jump))?;
+
+    Ok(Instruction::C { dest, comp, jump })
+}
@@ -0,0 +1,12 @@
+//! The Hack platform that translated programs run on.
+//! Contains the typed instructions emitters produce, the buffer they are cleaned up in before
+//! being written, an assembler for the generated assembly, and an emulator of the Hack CPU.
+
+pub mod assembler;
+pub mod buffer;
+pub mod emulator;
+pub mod instruction;
+pub mod peephole;
+
+/// Number of words of instruction memory
+pub const ROM_SIZE: usize = 0x8000;
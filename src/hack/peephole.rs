@@ -0,0 +1,162 @@
+//! Removes instructions that have no effect from emitted assembly.
+//!
+//! Instructions are only ever left out, never changed or reordered, so every instruction that
+//! remains still does exactly what the emitter meant it to. Code is only looked at within a
+//! basic block, the run of instructions between two labels, since a label can be jumped to
+//! from anywhere.
+
+use super::buffer::Line;
+use super::instruction::{comp, Instruction, Operand, DEST_A, DEST_D, DEST_M};
+
+/// Decide which lines to keep, repeating the rules until none of them finds anything more
+pub fn optimize(lines: &[Line]) -> Vec<bool> {
+    let mut keep = vec![true; lines.len()];
+
+    loop {
+        let mut changed = false;
+        for block in blocks(lines, &keep) {
+            changed |= cancel_push_pop(lines, &block, &mut keep);
+            changed |= redundant_loads(lines, &block, &mut keep);
+            changed |= dead_d_writes(lines, &block, &mut keep);
+        }
+        changed |= jumps_to_next(lines, &mut keep);
+
+        if !changed {
+            return keep;
+        }
+    }
+}
+
+// the instruction on line i
+fn at(lines: &[Line], i: usize) -> &Instruction {
+    &lines[i].instruction
+}
+
+// `M=M+1` and `M=M-1`
+const INCREMENT_M: Instruction = Instruction::C { dest: DEST_M, comp: comp("M+1"), jump: 0 };
+const DECREMENT_M: Instruction = Instruction::C { dest: DEST_M, comp: comp("M-1"), jump: 0 };
+
+// the indices of the kept instructions of each basic block
+fn blocks(lines: &[Line], keep: &[bool]) -> Vec<Vec<usize>> {
+    let mut blocks = vec![Vec::new()];
+    for (i, line) in lines.iter().enumerate() {
+        match line.instruction {
+            Instruction::Label(_) => blocks.push(Vec::new()),
+            _ if line.instruction.is_code() && keep[i] => blocks.last_mut().unwrap().push(i),
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+// `@SP / M=M+1 / @SP / M=M-1` leaves memory as it was, only `@SP` is needed to set A
+fn cancel_push_pop(lines: &[Line], block: &[usize], keep: &mut [bool]) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i + 4 <= block.len() {
+        let window: Vec<&Instruction> = block[i..i + 4].iter().map(|&l| at(lines, l)).collect();
+        let cancels = matches!(window[0], Instruction::A(_))
+            && window[0] == window[2]
+            && ((*window[1] == INCREMENT_M && *window[3] == DECREMENT_M)
+                || (*window[1] == DECREMENT_M && *window[3] == INCREMENT_M));
+
+        if cancels {
+            for &l in block[i + 1..i + 4].iter() {
+                keep[l] = false;
+            }
+            changed = true;
+            i += 4;
+        } else {
+            i += 1;
+        }
+    }
+
+    changed
+}
+
+// `@X` when A already holds X
+fn redundant_loads(lines: &[Line], block: &[usize], keep: &mut [bool]) -> bool {
+    let mut changed = false;
+    let mut a: Option<&Operand> = None;
+    for &l in block {
+        if !keep[l] {
+            continue;
+        }
+        match at(lines, l) {
+            Instruction::A(operand) if a == Some(operand) => {
+                keep[l] = false;
+                changed = true;
+            }
+            Instruction::A(operand) => a = Some(operand),
+            c if c.writes(DEST_A) => a = None,
+            _ => {}
+        }
+    }
+
+    changed
+}
+
+// `D=...` when D is written again before it is read
+fn dead_d_writes(lines: &[Line], block: &[usize], keep: &mut [bool]) -> bool {
+    let mut changed = false;
+    for (n, &l) in block.iter().enumerate() {
+        match at(lines, l) {
+            Instruction::C { dest: DEST_D, jump: 0, .. } if keep[l] => {}
+            _ => continue,
+        }
+
+        for &next in block[n + 1..].iter().filter(|&&next| keep[next]) {
+            let c = at(lines, next);
+            // a jump may land on code that reads D
+            if c.reads_d() || c.jumps() {
+                break;
+            }
+            if c.writes(DEST_D) {
+                keep[l] = false;
+                changed = true;
+                break;
+            }
+        }
+    }
+
+    changed
+}
+
+// `@L / 0;JMP / (L)` jumps to where execution would go anyway
+fn jumps_to_next(lines: &[Line], keep: &mut [bool]) -> bool {
+    let mut changed = false;
+    let instructions: Vec<usize> = (0..lines.len())
+        .filter(|&i| keep[i] && at(lines, i).is_code())
+        .collect();
+
+    for pair in instructions.windows(2) {
+        let (load, jump) = (pair[0], pair[1]);
+        let target = match at(lines, load) {
+            Instruction::A(Operand::Symbol(target)) => target,
+            _ => continue,
+        };
+        match at(lines, jump) {
+            Instruction::C { dest: 0, jump, .. } if *jump != 0 => {}
+            _ => continue,
+        }
+        // code jumping to a label between them would reach the jump with another A. Neither
+        // is the pair split by the comment that starts a command
+        if lines[load + 1..jump].iter().any(|line| !line.instruction.is_code()) {
+            continue;
+        }
+
+        // the labels between the jump and the next instruction
+        let lands_next = lines[jump + 1..].iter().enumerate()
+            .take_while(|(offset, line)| !line.instruction.is_code() || !keep[jump + 1 + offset])
+            .any(|(_, line)| matches!(&line.instruction, Instruction::Label(symbol) if symbol == target));
+
+        if lands_next && keep[load] && keep[jump] {
+            keep[load] = false;
+            keep[jump] = false;
+            changed = true;
+        }
+    }
+
+    changed
+}
@@ -0,0 +1,202 @@
+//! Translates nand2tetris VM code into Hack assembly.
+//!
+//! Sources are translated in memory:
+//!
+//! ```
+//! use vm_translator::{translate, Options, Source};
+//!
+//! let sources = [Source::new("Main.vm", "push constant 7\npush constant 8\nadd\n")];
+//! let asm = translate(&sources, &Options::default()).unwrap();
+//! assert!(asm.contains("@7"));
+//! ```
+
+pub mod transformer;
+pub mod hack;
+pub mod vm;
+pub mod difftest;
+pub mod testscript;
+pub mod sources;
+pub mod sizes;
+
+use std::io::Write;
+
+use transformer::{Backend, EmitOptions, Lowering};
+use vm::{callgraph, VmFile};
+use vm::optimize::{self, OptLevel};
+
+pub use transformer::{TransformError, TransformResult};
+pub use transformer::diagnostic::{Diagnostic, Severity};
+pub use sources::Source;
+pub use sizes::SizeTable;
+
+/// Whether bootstrap code that sets up the stack and calls `Sys.init` is emitted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Bootstrap {
+    /// Only if one of the sources declares `Sys.init`
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Bootstrap {
+    /// Decide for a parsed program
+    pub fn resolve(self, files: &[VmFile]) -> bool {
+        match self {
+            Bootstrap::Auto => vm::defines_function(files, SYS_INIT),
+            Bootstrap::Always => true,
+            Bootstrap::Never => false,
+        }
+    }
+}
+
+/// The function the bootstrap code calls
+const SYS_INIT: &str = "Sys.init";
+
+/// How sources are translated
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub bootstrap: Bootstrap,
+    /// The emitter code is generated with
+    pub backend: Backend,
+    /// Whether commands like the comparisons get their own code or call shared subroutines
+    pub lowering: Lowering,
+    /// How hard the commands are optimized before they are translated. From `Basic` on, the
+    /// emitted instructions are cleaned up as well
+    pub opt_level: OptLevel,
+    /// Translate every function, even those that can not be reached from `Sys.init`
+    pub keep_unreachable: bool,
+    /// Most words of ROM the program may take
+    pub rom_budget: usize,
+    /// Whether going over the ROM budget fails the translation or is only a warning
+    pub over_budget: Severity,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            bootstrap: Bootstrap::default(),
+            backend: Backend::default(),
+            lowering: Lowering::default(),
+            opt_level: OptLevel::default(),
+            keep_unreachable: false,
+            rom_budget: hack::ROM_SIZE,
+            over_budget: Severity::Error,
+        }
+    }
+}
+
+/// What a successful translation produced besides the assembly
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub warnings: Vec<Diagnostic>,
+    /// Number of source files translated
+    pub files: usize,
+    /// Number of VM commands translated
+    pub commands: usize,
+    /// Number of Hack instructions emitted, which is the size of the program in ROM
+    pub instructions: usize,
+    /// Whether bootstrap code was emitted
+    pub bootstrap: bool,
+    /// Functions left out because they can not be reached from `Sys.init`, in declaration order
+    pub removed_functions: Vec<String>,
+    /// Number of rewrites the optimizer applied
+    pub rewrites: usize,
+    pub sizes: SizeTable,
+}
+
+/// Translate the sources, in order, into one assembly program. Warnings are discarded.
+pub fn translate(sources: &[Source], options: &Options) -> TransformResult<String> {
+    let mut out = Vec::new();
+    translate_to(sources, options, &mut out)?;
+
+    Ok(String::from_utf8(out).expect("Emitted assembly is always valid utf-8"))
+}
+
+/// Translate the sources, in order, writing the assembly to `out`.
+///
+/// Every source is parsed before any code is written, so that all syntax errors are reported
+/// together and the bootstrap can be decided for the whole program.
+/// A program over the ROM budget is still written in full before the error is returned.
+pub fn translate_to<W: Write>(sources: &[Source], options: &Options, mut out: W) -> TransformResult<Report> {
+    let mut warnings = Vec::new();
+    let mut files = parse_sources(sources, &mut warnings)?;
+
+    let bootstrap = options.bootstrap.resolve(&files);
+    if options.bootstrap == Bootstrap::Always && !vm::defines_function(&files, SYS_INIT) {
+        let message = format!("bootstrap code calls {}, but no source declares it", SYS_INIT);
+        warnings.push(Diagnostic::unlocated(Severity::Warning, message));
+    }
+
+    // without Sys.init there is no entry point to start from, as in the project 7 tests
+    let mut removed_functions = Vec::new();
+    if !options.keep_unreachable && vm::defines_function(&files, SYS_INIT) {
+        removed_functions = callgraph::eliminate_unreachable(&mut files, SYS_INIT);
+    }
+
+    let rewrites = optimize::optimize_program(&mut files, options.opt_level);
+
+    let emit_options = EmitOptions {
+        lowering: options.lowering,
+        peephole: options.opt_level >= OptLevel::Basic,
+    };
+    let mut context = options.backend.context_with(emit_options);
+    let mut errors = Vec::new();
+    for file in files.iter() {
+        match context.write_file(&file.commands, &mut out, bootstrap, &file.name) {
+            Ok(()) => {}
+            Err(TransformError::Multiple(file_errors)) => errors.extend(file_errors),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(TransformError::from_errors(errors));
+    }
+
+    out.flush().map_err(|e| TransformError::IoError(e.to_string()))?;
+
+    let sizes = SizeTable::measure(&files, context.command_addresses(), context.instruction_count());
+    if sizes.total() > options.rom_budget {
+        if options.over_budget == Severity::Error {
+            return Err(TransformError::RomBudgetExceeded { budget: options.rom_budget, sizes });
+        }
+        let message = format!(
+            "the program needs {} words of ROM, more than the budget of {}",
+            sizes.total(),
+            options.rom_budget
+        );
+        warnings.push(Diagnostic::unlocated(Severity::Warning, message));
+    }
+
+    Ok(Report {
+        warnings,
+        files: files.len(),
+        commands: context.command_addresses().len(),
+        instructions: context.instruction_count(),
+        bootstrap,
+        removed_functions,
+        rewrites,
+        sizes,
+    })
+}
+
+/// Parse every source, reporting the errors in all of them together
+pub fn parse_sources(sources: &[Source], warnings: &mut Vec<Diagnostic>) -> TransformResult<Vec<VmFile>> {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+
+    for source in sources {
+        match VmFile::from_source(source, warnings) {
+            Ok(file) => files.push(file),
+            Err(TransformError::Multiple(file_errors)) => errors.extend(file_errors),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(TransformError::from_errors(errors));
+    }
+
+    Ok(files)
+}
@@ -1,45 +1,722 @@
-#![allow(unused)]
-
-use crate::transform::transform_directory;
-use crate::transformer::writer::WriterContext;
-use crate::transform::transform_file;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::sync::Arc;
 
-mod transformer;
-use transformer::{transform};
+use vm_translator::{difftest, testscript, vm};
+use vm_translator::{Bootstrap, Options, Report, Severity, TransformError};
+use vm_translator::hack::ROM_SIZE;
+use vm_translator::sources::{read_sources, Discovery};
+use vm_translator::transformer::{Backend, Lowering};
+use vm_translator::transformer::emit::HALT_LABEL;
+use vm_translator::vm::optimize::{self, OptLevel};
+use vm_translator::hack::assembler;
+use vm_translator::hack::emulator::{Emulator, StopReason};
+use vm_translator::vm::interpreter::{Interpreter, StopReason as VmStopReason};
+
+/// Cycles to run for when `run` is not given `--cycles`
+const DEFAULT_RUN_CYCLES: u64 = 1_000_000;
+
+/// The VM code has syntax or semantic errors, or a check, difftest or test script failed
+const EXIT_FAILED: i32 = 1;
+/// The command line could not be understood
+const EXIT_USAGE: i32 = 2;
+/// A file could not be read or written
+const EXIT_IO_ERROR: i32 = 3;
+/// A bug in the translator, such as a panic or assembly that does not assemble
+const EXIT_INTERNAL_ERROR: i32 = 4;
+
+const USAGE: &str = "\
+Translate nand2tetris VM code to Hack assembly.
+
+Usage:
+    vm_translator [translate] <path> [options]    translate a .vm file, or every .vm file in a folder
+    vm_translator check <path> [options]          report errors without writing any output
+    vm_translator run <path> [options]            translate and execute on the Hack emulator
+    vm_translator difftest <path> [options]       compare each backend with the VM interpreter
+    vm_translator test <script.tst | folder>...   run CPU emulator test scripts
+    vm_translator help                            show this text
+
+Options:
+    -o, --output <path>     where to write the output, '-' for stdout. Defaults to the input
+                            path with an .asm or .hack extension (translate)
+    --emit <asm|hack>       write assembly, or assemble it to Hack machine code (translate)
+    --init, --no-init       always or never emit bootstrap code that calls Sys.init. By default
+                            it is emitted only if a function Sys.init is declared
+    --backend <name>        the code generator: simple or compact. Defaults to simple
+    --lowering <mode>       inline: give every comparison its own code. shared: call subroutines
+                            shared by the whole program, which is smaller but slower.
+                            Defaults to inline
+    --opt-level <0|1|2>     0: translate commands as written. 1: fold constant arithmetic, drop
+                            commands that cancel out and instructions that have no effect.
+                            2: also fuse common command sequences. Defaults to 0
+    --quiet                 only print errors
+    --stats                 print the number of files, commands and instructions translated
+    --sizes                 print the number of instructions of every file and function
+    --rom-budget <words>    most words of ROM the program may take. Defaults to 32768
+    --over-budget <error|warn>
+                            whether a program over the ROM budget fails or only warns
+    --keep-unreachable      translate functions that can not be reached from Sys.init. They are
+                            left out by default
+    --interpret             execute the VM code directly instead of translating it (run)
+    --cycles <n>            most instructions to execute (run)
+    --set <address=value>   set RAM before execution, may be repeated (run)
+    --ram <start..end>      RAM to print after execution, may be repeated (run)
+    --steps <n>             most VM commands to execute (difftest)
+    --peephole              clean up the emitted instructions as from --opt-level 1 when
+                            translating. The machines are then only compared once the program
+                            halts (difftest)
+    --keep                  write each backend's translation next to the input as
+                            name.backend.asm (difftest)
+
+Finding .vm files in a folder:
+    --recursive             also use .vm files in subfolders
+    --include <glob>        only use files matching the pattern, may be repeated
+    --exclude <glob>        leave out files matching the pattern, may be repeated
+    --first <file>          put this file before the others, after Sys.vm and any named earlier.
+                            May be repeated
+    --no-first              do not put Sys.vm, or files named before, first
+
+    Files are ordered by path. Patterns without a '/' match the file name, and others the path
+    relative to the folder. '*' and '?' match within a folder name, and '**' across folders.
+
+Exit codes:
+    0    success
+    1    the VM code has errors, or a check or test failed
+    2    invalid command line
+    3    a file could not be read or written
+    4    internal error";
+
+enum CliError {
+    Usage(String),
+    /// Errors in the VM code
+    Source(TransformError),
+    Io(String),
+    Internal(String),
+    /// A test failed, and has already been reported
+    Failed,
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::Source(TransformError::IoError(_)) => EXIT_IO_ERROR,
+            CliError::Source(_) => EXIT_FAILED,
+            CliError::Io(_) => EXIT_IO_ERROR,
+            CliError::Internal(_) => EXIT_INTERNAL_ERROR,
+            CliError::Failed => EXIT_FAILED,
+        }
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CliError::Usage(msg) => write!(f, "{}\nRun 'vm_translator help' for usage.", msg),
+            CliError::Source(error) => write!(f, "{}", error),
+            CliError::Io(msg) => write!(f, "IO Error: {}", msg),
+            CliError::Internal(msg) => write!(f, "Internal Error: {}", msg),
+            CliError::Failed => Ok(()),
+        }
+    }
+}
+
+type CliResult = Result<(), CliError>;
 
 fn main() {
-    let mut translate_error = false;
-    let mut args = std::env::args().skip(1);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // a panic is a bug, and gets its own exit code. the default hook has already printed it
+    let code = match std::panic::catch_unwind(|| dispatch(args)) {
+        Ok(Ok(())) => 0,
+        Ok(Err(error)) => {
+            if !matches!(error, CliError::Failed) {
+                eprintln!("{}", error);
+            }
+            error.exit_code()
+        }
+        Err(_) => EXIT_INTERNAL_ERROR,
+    };
+
+    exit(code);
+}
+
+fn dispatch(args: Vec<String>) -> CliResult {
+    let mut args = args.into_iter().peekable();
+
+    match args.peek().map(|s| s.as_str()) {
+        None => Err(CliError::Usage("A file or folder must be supplied.".to_string())),
+        Some("help" | "-h" | "--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some("translate") => translate_command(args.skip(1).collect()),
+        Some("check") => check(args.skip(1).collect()),
+        Some("run") => run(args.skip(1).collect()),
+        Some("difftest") => difftest(args.skip(1).collect()),
+        Some("test") => test_scripts(args.skip(1).collect()),
+        // a bare path translates it
+        Some(_) => translate_command(args.collect()),
+    }
+}
+
+/// Options shared by the commands that translate a program
+struct Common {
+    path: Option<PathBuf>,
+    bootstrap: Bootstrap,
+    backend: Backend,
+    lowering: Lowering,
+    opt_level: OptLevel,
+    quiet: bool,
+    stats: bool,
+    sizes: bool,
+    keep_unreachable: bool,
+    rom_budget: usize,
+    over_budget: Severity,
+    discovery: Discovery,
+}
+
+impl Common {
+    fn new() -> Common {
+        Common {
+            path: None,
+            bootstrap: Bootstrap::Auto,
+            backend: Backend::default(),
+            lowering: Lowering::default(),
+            opt_level: OptLevel::default(),
+            quiet: false,
+            stats: false,
+            sizes: false,
+            keep_unreachable: false,
+            rom_budget: ROM_SIZE,
+            over_budget: Severity::Error,
+            discovery: Discovery::default(),
+        }
+    }
+
+    // take a shared option or the path. Returns false if the argument is not one
+    fn parse(&mut self, arg: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, CliError> {
+        match arg {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "--init" => self.bootstrap = Bootstrap::Always,
+            "--no-init" => self.bootstrap = Bootstrap::Never,
+            "--backend" => self.backend = parse_arg(args.next(), "--backend", Backend::from_name)?,
+            "--lowering" => self.lowering = parse_arg(args.next(), "--lowering", Lowering::from_name)?,
+            "--opt-level" => self.opt_level = parse_arg(args.next(), "--opt-level", OptLevel::from_number)?,
+            "--quiet" => self.quiet = true,
+            "--stats" => self.stats = true,
+            "--sizes" => self.sizes = true,
+            "--keep-unreachable" => self.keep_unreachable = true,
+            "--rom-budget" => self.rom_budget = parse_arg(args.next(), "--rom-budget", |v| v.parse().ok())?,
+            "--over-budget" => self.over_budget = parse_arg(args.next(), "--over-budget", |v| match v {
+                "error" => Some(Severity::Error),
+                "warn" => Some(Severity::Warning),
+                _ => None,
+            })?,
+            _ if parse_discovery(&mut self.discovery, arg, args)? => {}
+            "-" => return Err(CliError::Usage("VM code can not be read from stdin. '-' may only be given as the output path.".to_string())),
+            _ if arg.starts_with('-') => return Ok(false),
+            _ if self.path.is_none() => self.path = Some(PathBuf::from(arg)),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn path(&self) -> Result<&Path, CliError> {
+        self.path.as_deref()
+            .ok_or_else(|| CliError::Usage("A file or folder must be supplied.".to_string()))
+    }
 
-    let arg1 = match args.next() {
-        Some(arg) => arg,
+    fn options(&self) -> Options {
+        Options {
+            bootstrap: self.bootstrap,
+            backend: self.backend,
+            lowering: self.lowering,
+            opt_level: self.opt_level,
+            keep_unreachable: self.keep_unreachable,
+            rom_budget: self.rom_budget,
+            over_budget: self.over_budget,
+        }
+    }
+
+    // read and translate the program, printing any warnings
+    fn translate(&self, to_stdout: bool) -> Result<(String, Report), CliError> {
+        let path = self.path()?;
+        let sources = read_sources(path, &self.discovery).map_err(CliError::Source)?;
+        if sources.is_empty() {
+            return Err(CliError::Io(format!("no .vm files found in '{}'", path.display())));
+        }
+
+        for source in sources.iter() {
+            self.note(to_stdout, format_args!("Transforming file '{}'", source.name));
+        }
+
+        let mut out = Vec::new();
+        let report = match vm_translator::translate_to(&sources, &self.options(), &mut out) {
+            Ok(report) => report,
+            Err(error) => {
+                // show where the space went
+                if let TransformError::RomBudgetExceeded { sizes, .. } = &error {
+                    eprintln!("{}", sizes);
+                }
+                return Err(CliError::Source(error));
+            }
+        };
+
+        if !self.quiet {
+            for warning in report.warnings.iter() {
+                eprintln!("{}\n", warning);
+            }
+        }
+        if !report.removed_functions.is_empty() {
+            self.note(to_stdout, format_args!(
+                "Removed {} unreachable functions: {}",
+                report.removed_functions.len(),
+                report.removed_functions.join(", ")
+            ));
+        }
+        if self.sizes {
+            print_info(to_stdout, report.sizes.to_string().trim_end());
+        }
+        if self.stats {
+            print_stats(&report, to_stdout);
+        }
+
+        let asm = String::from_utf8(out).expect("Emitted assembly is always valid utf-8");
+        Ok((asm, report))
+    }
+
+    // progress messages go to stderr when stdout carries the output
+    fn note(&self, to_stdout: bool, message: std::fmt::Arguments) {
+        if self.quiet {
+            return;
+        }
+        if to_stdout {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+}
+
+fn print_stats(report: &Report, to_stdout: bool) {
+    let stats = format!(
+        "files:        {}\ncommands:     {}\ninstructions: {}\nbootstrap:    {}\nremoved:      {} functions\nrewrites:     {}",
+        report.files,
+        report.commands,
+        report.instructions,
+        if report.bootstrap { "yes" } else { "no" },
+        report.removed_functions.len(),
+        report.rewrites
+    );
+    print_info(to_stdout, &stats);
+}
+
+// information that was asked for. it goes to stderr when stdout carries the output
+fn print_info(to_stdout: bool, text: &str) {
+    if to_stdout {
+        eprintln!("{}", text);
+    } else {
+        println!("{}", text);
+    }
+}
+
+// `translate <path> [-o PATH] [--emit asm|hack] [--init|--no-init] [--backend NAME] [--quiet] [--stats] [--sizes]`
+fn translate_command(args: Vec<String>) -> CliResult {
+    let mut common = Common::new();
+    let mut output = None;
+    let mut emit_hack = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if common.parse(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(parse_arg(args.next(), &arg, |v| Some(v.to_string()))?),
+            "--emit" => emit_hack = parse_arg(args.next(), "--emit", |v| match v {
+                "asm" => Some(false),
+                "hack" => Some(true),
+                _ => None,
+            })?,
+            _ => return Err(CliError::Usage(format!("Unexpected argument '{}'", arg))),
+        }
+    }
+
+    let to_stdout = output.as_deref() == Some("-");
+    let (asm, _) = common.translate(to_stdout)?;
+
+    let text = if emit_hack {
+        assemble(&asm)?.to_hack_text()
+    } else {
+        asm
+    };
+
+    if to_stdout {
+        return std::io::stdout().write_all(text.as_bytes())
+            .map_err(|e| CliError::Io(format!("failed to write to stdout: {}", e)));
+    }
+
+    let out_path = match output {
+        Some(path) => PathBuf::from(path),
         None => {
-            eprintln!("A file or folder must be supplied as the first argument.");
-            exit(1);
+            let out_path = assume_output_path(common.path()?);
+            if emit_hack { out_path.with_extension("hack") } else { out_path }
+        }
+    };
+    common.note(false, format_args!("Writing '{}'", out_path.display()));
+    std::fs::write(&out_path, text)
+        .map_err(|e| CliError::Io(format!("failed to write '{}': {}", out_path.display(), e)))
+}
+
+// `check <path> [--init|--no-init] [--backend NAME] [--quiet] [--stats]`
+// translate without writing anything, to report errors and warnings
+fn check(args: Vec<String>) -> CliResult {
+    let mut common = Common::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !common.parse(&arg, &mut args)? {
+            return Err(CliError::Usage(format!("Unexpected argument '{}'", arg)));
+        }
+    }
+
+    let (_, report) = common.translate(false)?;
+    common.note(false, format_args!(
+        "No errors in {} files ({} warnings)",
+        report.files,
+        report.warnings.len()
+    ));
+
+    Ok(())
+}
+
+// assemble translated code. the translator's own output failing to assemble is a bug
+fn assemble(asm: &str) -> Result<assembler::AssembledProgram, CliError> {
+    assembler::assemble(asm).map_err(|e| CliError::Internal(format!("generated assembly is invalid: {}", e)))
+}
+
+// `run <path> [--init|--no-init] [--backend NAME] [--interpret] [--cycles N] [--set ADDRESS=VALUE]... [--ram START..END]...`
+// translate the program, then execute it on the built-in emulator and dump RAM.
+// with --interpret the VM commands are executed directly instead, and cycles count commands
+fn run(args: Vec<String>) -> CliResult {
+    let mut common = Common::new();
+    let mut interpret = false;
+    let mut cycles = DEFAULT_RUN_CYCLES;
+    let mut pokes: Vec<(u16, i16)> = Vec::new();
+    let mut ranges: Vec<(u16, u16)> = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if common.parse(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--interpret" => interpret = true,
+            "--cycles" => cycles = parse_arg(args.next(), "--cycles", |v| v.parse().ok())?,
+            "--set" => pokes.push(parse_arg(args.next(), "--set", |v| {
+                let (address, value) = v.split_once('=')?;
+                Some((address.parse().ok()?, value.parse().ok()?))
+            })?),
+            "--ram" => ranges.push(parse_arg(args.next(), "--ram", |v| {
+                let (start, end) = v.split_once("..")?;
+                Some((start.parse().ok()?, end.parse().ok()?))
+            })?),
+            _ => return Err(CliError::Usage(format!("Unexpected argument '{}'", arg))),
         }
+    }
+
+    if ranges.is_empty() {
+        ranges.push((0, 16));
+    }
+
+    if interpret {
+        return interpret_program(&common, cycles, &pokes, &ranges);
+    }
+
+    let (asm, _) = common.translate(false)?;
+    let program = assemble(&asm)?;
+
+    let halt_address = program.symbols.get(HALT_LABEL).copied();
+    let mut emulator = Emulator::new(program.rom);
+    for (address, value) in pokes {
+        emulator.poke(address, value);
+    }
+
+    let reason = emulator.run(cycles, halt_address);
+    let reason = match reason {
+        StopReason::CycleLimit => "cycle limit reached",
+        StopReason::Halted => "halted",
+        StopReason::EndOfProgram => "end of program",
     };
+    println!("Stopped after {} cycles: {}", emulator.cycles(), reason);
 
-    let inject_init = match args.next().as_deref() {
-        Some("--init") => true,
-        _ => false,
+    for (start, end) in ranges {
+        for address in start..end {
+            println!("RAM[{}] = {}", address, emulator.peek(address));
+        }
+    }
+
+    Ok(())
+}
+
+// execute the VM commands directly with the reference interpreter
+fn interpret_program(common: &Common, steps: u64, pokes: &[(u16, i16)], ranges: &[(u16, u16)]) -> CliResult {
+    let files = vm::parse_path(common.path()?, &common.discovery).map_err(CliError::Source)?;
+    let inject_init = common.bootstrap.resolve(&files);
+
+    let result = Interpreter::new(&files).and_then(|mut interpreter| {
+        for (address, value) in pokes {
+            interpreter.poke(*address, *value);
+        }
+        if inject_init {
+            interpreter.bootstrap()?;
+        }
+        let reason = interpreter.run(steps)?;
+        Ok((interpreter, reason))
+    });
+
+    let (interpreter, reason) = match result {
+        Ok(v) => v,
+        Err(error) => {
+            eprintln!("{}", error);
+            return Err(CliError::Failed);
+        }
+    };
+
+    let reason = match reason {
+        VmStopReason::StepLimit => "step limit reached",
+        VmStopReason::Halted => "halted",
+        VmStopReason::EndOfProgram => "end of program",
+        VmStopReason::Returned => "returned from outermost function",
     };
+    println!("Stopped after {} commands: {}", interpreter.steps(), reason);
 
+    for (start, end) in ranges {
+        for address in *start..*end {
+            println!("RAM[{}] = {}", address, interpreter.peek(address));
+        }
+    }
+    for (symbol, value) in interpreter.statics() {
+        println!("{} = {}", symbol, value);
+    }
 
-    let context = WriterContext::default();
-    let path = Path::new(&arg1);
-    let out_path = assume_output_path(path);
-    let out_steam = Arc::new(std::fs::File::create(&out_path).unwrap());
-    if path.is_dir() {
-        transform_directory(path, &mut translate_error, out_steam, inject_init, context, out_path.clone());
-    } else {
-        transform_file(context, path, out_steam, &mut translate_error, inject_init, out_path.clone());
+    Ok(())
+}
+
+// `difftest <path> [--init|--no-init] [--steps N] [--backend NAME]... [--lowering MODE] [--opt-level N] [--peephole] [--keep] [--recursive] [--include GLOB]... [--exclude GLOB]...`
+// run the program on the VM interpreter and on each emitter's translation, comparing them after each command,
+// or only once the program halts with --peephole.
+// every backend is checked unless some are named. the interpreter runs the optimized commands too
+fn difftest(args: Vec<String>) -> CliResult {
+    let mut path = None;
+    let mut bootstrap = Bootstrap::Auto;
+    let mut discovery = Discovery::default();
+    let mut opt_level = OptLevel::default();
+    let mut keep = false;
+    let mut options = difftest::DiffOptions {
+        emit_init: false,
+        max_steps: DEFAULT_RUN_CYCLES,
+        backends: Vec::new(),
+        lowering: Lowering::default(),
+        peephole: false,
+        keep: None,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--init" => bootstrap = Bootstrap::Always,
+            "--no-init" => bootstrap = Bootstrap::Never,
+            "--steps" => options.max_steps = parse_arg(args.next(), "--steps", |v| v.parse().ok())?,
+            "--backend" => options.backends.push(parse_arg(args.next(), "--backend", Backend::from_name)?),
+            "--lowering" => options.lowering = parse_arg(args.next(), "--lowering", Lowering::from_name)?,
+            "--opt-level" => opt_level = parse_arg(args.next(), "--opt-level", OptLevel::from_number)?,
+            "--peephole" => options.peephole = true,
+            "--keep" => keep = true,
+            _ if parse_discovery(&mut discovery, &arg, &mut args)? => {}
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => return Err(CliError::Usage(format!("Unexpected argument '{}'", arg))),
+        }
+    }
+
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => return Err(CliError::Usage("A file or folder must be supplied to test.".to_string())),
+    };
+
+    if options.backends.is_empty() {
+        options.backends = Backend::ALL.to_vec();
     }
 
-    if translate_error {
-        std::process::exit(1);
+    let mut files = vm::parse_path(&path, &discovery).map_err(CliError::Source)?;
+    optimize::optimize_program(&mut files, opt_level);
+    options.emit_init = bootstrap.resolve(&files);
+    if keep {
+        options.keep = Some(assume_output_path(&path));
+    }
+    let results = difftest::check_all(&files, &options).map_err(|e| match e {
+        // not a bug, the program needs more steps or to be compared after each command
+        difftest::DiffError::Unfinished(msg) => CliError::Usage(msg),
+        e => CliError::Internal(e.to_string()),
+    })?;
+
+    let mut failed = false;
+    for (backend, divergence) in results {
+        match divergence {
+            Some(divergence) => {
+                failed = true;
+                eprint!("{}", divergence);
+            }
+            None => println!("[{}] matches the interpreter", backend),
+        }
+    }
+
+    if failed {
+        return Err(CliError::Failed);
+    }
+
+    Ok(())
+}
+
+// `test <script.tst | folder>... [--init|--no-init] [--backend NAME] [--lowering MODE]`
+// run CPU emulator test scripts, translating the programs they load. folders run every script in them,
+// except the `*VME.tst` scripts which are meant for the VM emulator
+fn test_scripts(args: Vec<String>) -> CliResult {
+    let mut scripts = Vec::new();
+    let mut bootstrap = Bootstrap::Auto;
+    let mut backend = Backend::default();
+    let mut lowering = Lowering::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--init" => bootstrap = Bootstrap::Always,
+            "--no-init" => bootstrap = Bootstrap::Never,
+            "--backend" => backend = parse_arg(args.next(), "--backend", Backend::from_name)?,
+            "--lowering" => lowering = parse_arg(args.next(), "--lowering", Lowering::from_name)?,
+            _ if arg.starts_with('-') => return Err(CliError::Usage(format!("Unexpected argument '{}'", arg))),
+            _ => {
+                let path = PathBuf::from(arg);
+                if path.is_dir() {
+                    let entries = path.read_dir()
+                        .map_err(|e| CliError::Io(format!("failed to read '{}': {}", path.display(), e)))?;
+                    let mut found: Vec<PathBuf> = entries
+                        .filter_map(|entry| entry.ok().map(|e| e.path()))
+                        .filter(|p| p.extension() == Some("tst".as_ref()))
+                        .filter(|p| !p.to_string_lossy().ends_with("VME.tst"))
+                        .collect();
+                    found.sort();
+                    scripts.extend(found);
+                } else {
+                    scripts.push(path);
+                }
+            }
+        }
+    }
+
+    if scripts.is_empty() {
+        return Err(CliError::Usage("At least one test script must be supplied.".to_string()));
+    }
+
+    let options = Options {
+        bootstrap,
+        backend,
+        lowering,
+        ..Options::default()
+    };
+    let load = |asm_path: &Path| -> Result<assembler::AssembledProgram, String> {
+        let asm_path = translate_for_script(asm_path, &options)?;
+        let source = std::fs::read_to_string(&asm_path)
+            .map_err(|e| format!("failed to read '{}': {}", asm_path.display(), e))?;
+        assembler::assemble(&source).map_err(|e| e.to_string())
+    };
+
+    let mut failed = false;
+    for script in scripts {
+        match testscript::run_script(&script, &load) {
+            Ok(result) => {
+                for echo in result.echoes.iter() {
+                    println!("{}", echo);
+                }
+                match (&result.compare_path, &result.mismatch) {
+                    (_, Some(mismatch)) => {
+                        failed = true;
+                        eprintln!("FAIL {}: output differs on line {}", script.display(), mismatch.line);
+                        eprintln!("    expected: {}", mismatch.expected);
+                        eprintln!("    actual:   {}", mismatch.actual);
+                    }
+                    (Some(_), None) => println!("PASS {}", script.display()),
+                    (None, None) => println!("DONE {} (nothing to compare to)", script.display()),
+                }
+            }
+            Err(error) => {
+                failed = true;
+                eprintln!("FAIL {}: {}", script.display(), error);
+            }
+        }
+    }
+
+    if failed {
+        return Err(CliError::Failed);
+    }
+
+    Ok(())
+}
+
+// translate the vm code a test script's `load X.asm` refers to. returns where the assembly was written.
+// a lone `X.vm` is translated as a file, several .vm files as their folder.
+fn translate_for_script(asm_path: &Path, options: &Options) -> Result<PathBuf, String> {
+    let directory = asm_path.parent().unwrap_or(Path::new("."));
+    let vm_files = directory.read_dir()
+        .map_err(|e| format!("failed to read '{}': {}", directory.display(), e))?
+        .filter(|entry| {
+            entry.as_ref().is_ok_and(|e| e.path().extension() == Some("vm".as_ref()))
+        })
+        .count();
+
+    let vm_path = asm_path.with_extension("vm");
+    let source = match vm_files {
+        0 => return Ok(asm_path.to_path_buf()),
+        1 if vm_path.exists() => vm_path,
+        _ => directory.to_path_buf(),
+    };
+
+    let sources = read_sources(&source, &Discovery::default()).map_err(|e| e.to_string())?;
+    let asm = vm_translator::translate(&sources, options)
+        .map_err(|e| format!("failed to translate '{}':\n{}", source.display(), e))?;
+
+    let out_path = assume_output_path(&source);
+    std::fs::write(&out_path, asm)
+        .map_err(|e| format!("failed to write '{}': {}", out_path.display(), e))?;
+
+    Ok(out_path)
+}
+
+// take an option controlling how .vm files are found. Returns false if the argument is not one
+fn parse_discovery(discovery: &mut Discovery, arg: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, CliError> {
+    let value = |args: &mut dyn Iterator<Item = String>| parse_arg(args.next(), arg, |v| Some(v.to_string()));
+
+    match arg {
+        "--recursive" => discovery.recursive = true,
+        "--include" => discovery.include.push(value(args)?),
+        "--exclude" => discovery.exclude.push(value(args)?),
+        "--no-first" => discovery.first.clear(),
+        "--first" => discovery.first.push(value(args)?),
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+// parse the value following a flag
+fn parse_arg<T>(value: Option<String>, flag: &str, parse: impl Fn(&str) -> Option<T>) -> Result<T, CliError> {
+    match value.as_deref().and_then(parse) {
+        Some(v) => Ok(v),
+        None => Err(CliError::Usage(format!("Invalid or missing value for '{}'", flag))),
     }
 }
 
@@ -59,5 +736,5 @@ fn assume_output_path(input_path: &Path) -> PathBuf {
         path.set_extension("asm");
     }
 
-    return path;
+    path
 }
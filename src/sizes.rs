@@ -0,0 +1,97 @@
+//! How much of the ROM each file and function of a translated program takes
+
+use std::cmp::Reverse;
+
+use crate::vm::VmFile;
+use crate::transformer::CommandDetails;
+
+/// Instructions emitted for each file and function, largest first
+#[derive(Clone, Debug, Default)]
+pub struct SizeTable {
+    /// Bootstrap code and any backend prelude, emitted before the first command
+    pub overhead: usize,
+    pub files: Vec<FileSize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FileSize {
+    /// The file stem, e.g. `Foo` for `Foo.vm`
+    pub name: String,
+    /// Every instruction emitted for the file, including any code outside of its functions
+    pub instructions: usize,
+    pub functions: Vec<FunctionSize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FunctionSize {
+    pub name: String,
+    pub instructions: usize,
+}
+
+impl SizeTable {
+    /// Attribute the instructions of a translation to the files and functions they came from.
+    /// `command_addresses` holds the ROM address of every command of `files`, in order, and
+    /// `total` is the size of the whole program.
+    pub fn measure(files: &[VmFile], command_addresses: &[usize], total: usize) -> SizeTable {
+        let address = |i: usize| command_addresses.get(i).copied().unwrap_or(total);
+        let mut table = SizeTable {
+            overhead: address(0),
+            files: Vec::new(),
+        };
+
+        let mut i = 0;
+        for file in files {
+            let mut size = FileSize {
+                name: file.name.clone(),
+                instructions: 0,
+                functions: Vec::new(),
+            };
+
+            for (command, _) in file.commands.iter() {
+                let instructions = address(i + 1) - address(i);
+                i += 1;
+
+                if let CommandDetails::Function { symbol, .. } = command {
+                    size.functions.push(FunctionSize { name: symbol.clone(), instructions: 0 });
+                }
+                if let Some(function) = size.functions.last_mut() {
+                    function.instructions += instructions;
+                }
+                size.instructions += instructions;
+            }
+
+            size.functions.sort_by_key(|function| Reverse(function.instructions));
+            table.files.push(size);
+        }
+
+        table.files.sort_by_key(|file| Reverse(file.instructions));
+        table
+    }
+
+    /// Size of the whole program
+    pub fn total(&self) -> usize {
+        self.overhead + self.files.iter().map(|f| f.instructions).sum::<usize>()
+    }
+}
+
+impl std::fmt::Display for SizeTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let total = self.total();
+        let row = |f: &mut std::fmt::Formatter, instructions: usize, name: &str| {
+            let share = if total == 0 { 0.0 } else { instructions as f64 * 100.0 / total as f64 };
+            writeln!(f, "{:>8} {:>6.1}%  {}", instructions, share, name)
+        };
+
+        writeln!(f, "{:>8} {:>7}  name", "words", "share")?;
+        row(f, total, "total")?;
+        row(f, self.overhead, "bootstrap and prelude")?;
+        for file in self.files.iter() {
+            row(f, file.instructions, &format!("{}.vm", file.name))?;
+            for function in file.functions.iter() {
+                row(f, function.instructions, &format!("  {}", function.name))?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! Finding and reading the `.vm` files a program is made of
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::transformer::diagnostic::Diagnostic;
+use crate::transformer::{TransformError, TransformResult};
+
+/// A VM source file held in memory
+#[derive(Clone, Debug)]
+pub struct Source {
+    /// The file name, e.g. `Foo.vm`. Used in diagnostics, and its stem scopes the file's statics
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: impl Into<String>, text: impl Into<String>) -> Source {
+        Source {
+            name: name.into(),
+            text: text.into(),
+        }
+    }
+
+    /// Read the whole of a source from a stream
+    pub fn from_reader(name: impl Into<String>, mut reader: impl Read) -> TransformResult<Source> {
+        let name = name.into();
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)
+            .map_err(|e| TransformError::IoError(format!("failed to read '{}': {}", name, e)))?;
+        let text = decode(bytes, Path::new(&name))?;
+
+        Ok(Source::new(name, text))
+    }
+
+    /// Read a source file, naming it by its path
+    pub fn from_path(path: &Path) -> TransformResult<Source> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| TransformError::IoError(format!("failed to read '{}': {}", path.display(), e)))?;
+        let text = decode(bytes, path)?;
+
+        Ok(Source::new(path.display().to_string(), text))
+    }
+
+    /// `Foo` for `dir/Foo.vm`
+    pub fn stem(&self) -> &str {
+        Path::new(&self.name).file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(&self.name)
+    }
+}
+
+/// The text of a source read as bytes. Text that is not utf-8 is a syntax error pointing at the
+/// first byte that is not
+pub fn decode(bytes: Vec<u8>, path: &Path) -> TransformResult<String> {
+    String::from_utf8(bytes).map_err(|error| {
+        let start = error.utf8_error().valid_up_to();
+        let source = String::from_utf8_lossy(error.as_bytes());
+        let message = "source is not valid utf-8".to_string();
+        TransformError::SyntaxError(Diagnostic::error(message, path.to_path_buf(), &source, start..start + 1))
+    })
+}
+
+/// How the `.vm` files of a program are found in a directory.
+///
+/// Files are ordered by their path relative to the directory, so the same tree always gives
+/// the same program, except that the files named in `first` come before the rest.
+#[derive(Clone, Debug)]
+pub struct Discovery {
+    /// Also look in subdirectories
+    pub recursive: bool,
+    /// File names placed at the start, in this order
+    pub first: Vec<String>,
+    /// Glob patterns a file must match one of to be used. Every file is used if there are none
+    pub include: Vec<String>,
+    /// Glob patterns of files to leave out
+    pub exclude: Vec<String>,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery {
+            recursive: false,
+            first: vec!["Sys.vm".to_string()],
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl Discovery {
+    // patterns without a `/` match the file name, others the path relative to the directory
+    fn is_wanted(&self, relative: &str) -> bool {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        let matches = |pattern: &String| {
+            let text = if pattern.contains('/') { relative } else { name };
+            glob_match(&pattern.chars().collect::<Vec<_>>(), &text.chars().collect::<Vec<_>>())
+        };
+
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+
+    // files named in `first` come first, then the rest by path
+    fn sort_key(&self, relative: &str) -> (usize, String) {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        let rank = self.first.iter()
+            .position(|first| first == name)
+            .unwrap_or(self.first.len());
+
+        (rank, relative.to_string())
+    }
+}
+
+/// The `.vm` file at `path`, or the `.vm` files found in the directory at `path`
+pub fn find_vm_files(path: &Path, discovery: &Discovery) -> std::io::Result<Vec<PathBuf>> {
+    // a file named directly is always used
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut found = Vec::new();
+    collect_vm_files(path, "", discovery, &mut found)?;
+    found.sort_by_cached_key(|(relative, _)| discovery.sort_key(relative));
+
+    Ok(found.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Read the `.vm` file at `path`, or the `.vm` files found in the directory at `path`.
+/// Every file that can not be read is reported
+pub fn read_sources(path: &Path, discovery: &Discovery) -> TransformResult<Vec<Source>> {
+    let paths = find_vm_files(path, discovery)
+        .map_err(|e| TransformError::IoError(format!("failed to read '{}': {}", path.display(), e)))?;
+
+    let mut sources = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths.iter() {
+        match Source::from_path(path) {
+            Ok(source) => sources.push(source),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(TransformError::from_errors(errors));
+    }
+
+    Ok(sources)
+}
+
+// add the wanted files in `directory` to `out`, with their path relative to the root as `/` separated text
+fn collect_vm_files(
+    directory: &Path,
+    prefix: &str,
+    discovery: &Discovery,
+    out: &mut Vec<(String, PathBuf)>,
+) -> std::io::Result<()> {
+    for entry in directory.read_dir()? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        let relative = format!("{}{}", prefix, name);
+
+        if path.is_dir() {
+            if discovery.recursive {
+                collect_vm_files(&path, &format!("{}/", relative), discovery, out)?;
+            }
+        } else if path.extension() == Some("vm".as_ref()) && discovery.is_wanted(&relative) {
+            out.push((relative, path));
+        }
+    }
+
+    Ok(())
+}
+
+// `*` and `?` match within a path component, `**` matches across components
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `**/` also matches no directories at all
+            if let ['/', after @ ..] = rest {
+                if glob_match(after, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => {
+            let component = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..=component).any(|i| glob_match(rest, &text[i..]))
+        }
+        ['?', rest @ ..] => {
+            matches!(text.first(), Some(&c) if c != '/') && glob_match(rest, &text[1..])
+        }
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
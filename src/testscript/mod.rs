@@ -0,0 +1,211 @@
+//! Runs the nand2tetris CPU emulator test scripts (`.tst`) against the built-in emulator,
+//! writing the `.out` file and comparing it with the expected `.cmp` file.
+
+pub mod script;
+
+use std::path::{Path, PathBuf};
+
+use crate::hack::assembler::AssembledProgram;
+use crate::hack::emulator::Emulator;
+use script::{Command, OutputColumn, Variable};
+
+/// The first line where the output did not match the comparison file
+pub struct Mismatch {
+    /// 1-based line number in the output
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+pub struct ScriptResult {
+    pub output_path: Option<PathBuf>,
+    /// The comparison file, if the script named one
+    pub compare_path: Option<PathBuf>,
+    pub mismatch: Option<Mismatch>,
+    /// The text of the script's `echo` commands, in order
+    pub echoes: Vec<String>,
+}
+
+impl ScriptResult {
+    pub fn passed(&self) -> bool {
+        self.mismatch.is_none()
+    }
+}
+
+struct Runner<'a> {
+    directory: PathBuf,
+    load: &'a dyn Fn(&Path) -> Result<AssembledProgram, String>,
+    emulator: Emulator,
+    columns: Vec<OutputColumn>,
+    output: Vec<String>,
+    output_path: Option<PathBuf>,
+    compare_path: Option<PathBuf>,
+    echoes: Vec<String>,
+}
+
+/// Run a test script.
+/// `load` is given the path of each program the script loads and must return it assembled,
+/// translating it first if needed.
+pub fn run_script(
+    script_path: &Path,
+    load: &dyn Fn(&Path) -> Result<AssembledProgram, String>,
+) -> Result<ScriptResult, String> {
+    let source = std::fs::read_to_string(script_path)
+        .map_err(|e| format!("failed to read '{}': {}", script_path.display(), e))?;
+    let commands = script::parse(&source)
+        .map_err(|e| format!("{}: {}", script_path.display(), e))?;
+
+    let mut runner = Runner {
+        directory: script_path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        load,
+        emulator: Emulator::new(Vec::new()),
+        columns: Vec::new(),
+        output: Vec::new(),
+        output_path: None,
+        compare_path: None,
+        echoes: Vec::new(),
+    };
+    runner.execute(&commands)?;
+
+    let mut text = runner.output.join("\n");
+    text.push('\n');
+    if let Some(path) = &runner.output_path {
+        std::fs::write(path, &text).map_err(|e| format!("failed to write '{}': {}", path.display(), e))?;
+    }
+
+    let mismatch = match &runner.compare_path {
+        Some(path) => {
+            let expected = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read '{}': {}", path.display(), e))?;
+            compare(&text, &expected)
+        }
+        None => None,
+    };
+
+    Ok(ScriptResult {
+        output_path: runner.output_path,
+        compare_path: runner.compare_path,
+        mismatch,
+        echoes: runner.echoes,
+    })
+}
+
+impl Runner<'_> {
+    fn execute(&mut self, commands: &[Command]) -> Result<(), String> {
+        for command in commands {
+            match command {
+                Command::Load(file) => {
+                    let program = (self.load)(&self.directory.join(file))?;
+                    self.emulator = Emulator::new(program.rom);
+                }
+                Command::OutputFile(file) => self.output_path = Some(self.directory.join(file)),
+                Command::CompareTo(file) => self.compare_path = Some(self.directory.join(file)),
+                Command::OutputList(columns) => {
+                    self.columns = columns.clone();
+                    let header = self.columns.iter()
+                        .map(header_cell)
+                        .collect::<Vec<_>>();
+                    self.output.push(format!("|{}|", header.join("|")));
+                }
+                Command::Set(variable, value) => self.set(*variable, *value),
+                Command::TickTock => {
+                    self.emulator.step();
+                }
+                Command::Output => {
+                    let cells = self.columns.iter()
+                        .map(|c| value_cell(c, self.get(c.variable)))
+                        .collect::<Vec<_>>();
+                    self.output.push(format!("|{}|", cells.join("|")));
+                }
+                Command::Echo(text) => self.echoes.push(text.clone()),
+                Command::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        self.execute(body)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, variable: Variable) -> i16 {
+        match variable {
+            Variable::Ram(address) => self.emulator.peek(address),
+            Variable::A => self.emulator.a(),
+            Variable::D => self.emulator.d(),
+            Variable::PC => self.emulator.pc() as i16,
+            Variable::Time => self.emulator.cycles() as i16,
+        }
+    }
+
+    fn set(&mut self, variable: Variable, value: i16) {
+        match variable {
+            Variable::Ram(address) => self.emulator.poke(address, value),
+            Variable::PC => self.emulator.set_pc(value as u16),
+            // the emulator's registers are not writable from scripts
+            Variable::A | Variable::D | Variable::Time => {}
+        }
+    }
+}
+
+// the column name centred over the width of the column
+fn header_cell(column: &OutputColumn) -> String {
+    let width = column.pad_left + column.len + column.pad_right;
+    let name: String = column.name.chars().take(width).collect();
+    let left = (width - name.len()) / 2;
+    let right = width - name.len() - left;
+
+    format!("{}{}{}", " ".repeat(left), name, " ".repeat(right))
+}
+
+fn value_cell(column: &OutputColumn, value: i16) -> String {
+    let text = match column.format {
+        'X' => format!("{:04X}", value as u16),
+        'B' => format!("{:016b}", value as u16),
+        _ => value.to_string(),
+    };
+    // keep the least significant digits if the value does not fit
+    let text: String = text.chars().skip(text.len().saturating_sub(column.len)).collect();
+
+    format!(
+        "{}{:>len$}{}",
+        " ".repeat(column.pad_left),
+        text,
+        " ".repeat(column.pad_right),
+        len = column.len
+    )
+}
+
+/// Compare output with the expected text line by line.
+/// Cells are compared without their padding, and a cell of only `*` in the expected text matches anything.
+pub fn compare(actual: &str, expected: &str) -> Option<Mismatch> {
+    let cells = |line: &str| -> Vec<String> {
+        line.trim().split('|').map(|c| c.trim().to_string()).collect()
+    };
+
+    let actual_lines: Vec<&str> = actual.lines().filter(|l| !l.trim().is_empty()).collect();
+    let expected_lines: Vec<&str> = expected.lines().filter(|l| !l.trim().is_empty()).collect();
+
+    for i in 0..actual_lines.len().max(expected_lines.len()) {
+        let actual_line = actual_lines.get(i).copied().unwrap_or("");
+        let expected_line = expected_lines.get(i).copied().unwrap_or("");
+
+        let actual_cells = cells(actual_line);
+        let expected_cells = cells(expected_line);
+        let matches = actual_cells.len() == expected_cells.len()
+            && actual_cells.iter().zip(expected_cells.iter()).all(|(a, e)| {
+                a == e || (!e.is_empty() && e.chars().all(|c| c == '*'))
+            });
+
+        if !matches {
+            return Some(Mismatch {
+                line: i + 1,
+                expected: expected_line.to_string(),
+                actual: actual_line.to_string(),
+            });
+        }
+    }
+
+    None
+}
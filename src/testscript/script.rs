@@ -0,0 +1,263 @@
+//! Parser for the `.tst` test scripts shipped with the nand2tetris CPU emulator tests
+
+/// A location that scripts can read and write
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variable {
+    Ram(u16),
+    A,
+    D,
+    PC,
+    /// Number of clock cycles executed
+    Time,
+}
+
+/// One column of an `output-list`, e.g. `RAM[0]%D2.6.2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputColumn {
+    pub variable: Variable,
+    pub name: String,
+    /// One of `D`, `X`, `B` or `S`
+    pub format: char,
+    pub pad_left: usize,
+    pub len: usize,
+    pub pad_right: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Load(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<OutputColumn>),
+    Set(Variable, i16),
+    TickTock,
+    Output,
+    Echo(String),
+    Repeat(u64, Vec<Command>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Comma,
+    Semicolon,
+    Open,
+    Close,
+}
+
+/// Parse a whole test script
+pub fn parse(source: &str) -> Result<Vec<Command>, String> {
+    let tokens = tokenize(source)?;
+    let mut position = 0;
+    let commands = parse_block(&tokens, &mut position)?;
+    if position < tokens.len() {
+        return Err("unexpected '}'".to_string());
+    }
+
+    Ok(commands)
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = source.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != '"' {
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err("unterminated string".to_string());
+            }
+            tokens.push(Token::Str(chars[start..i].iter().collect()));
+            i += 1;
+        } else {
+            let single = match c {
+                ',' => Some(Token::Comma),
+                ';' => Some(Token::Semicolon),
+                '{' => Some(Token::Open),
+                '}' => Some(Token::Close),
+                _ => None,
+            };
+            if let Some(token) = single {
+                tokens.push(token);
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() && !",;{}\"".contains(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().collect()));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// parse commands until the end of the script or the end of a repeat block
+fn parse_block(tokens: &[Token], position: &mut usize) -> Result<Vec<Command>, String> {
+    let mut commands = Vec::new();
+
+    while let Some(token) = tokens.get(*position) {
+        let word = match token {
+            Token::Word(word) => word.clone(),
+            Token::Close => break,
+            Token::Comma | Token::Semicolon => {
+                *position += 1;
+                continue;
+            }
+            _ => return Err(format!("unexpected {:?}", token)),
+        };
+        *position += 1;
+
+        let mut args = Vec::new();
+        while let Some(Token::Word(arg) | Token::Str(arg)) = tokens.get(*position) {
+            args.push(arg.clone());
+            *position += 1;
+        }
+
+        if word == "repeat" {
+            let count = match args.as_slice() {
+                [count] => count.parse().map_err(|_| format!("invalid repeat count '{}'", count))?,
+                _ => return Err("repeat requires a count".to_string()),
+            };
+            if tokens.get(*position) != Some(&Token::Open) {
+                return Err("expected '{' after repeat".to_string());
+            }
+            *position += 1;
+            let body = parse_block(tokens, position)?;
+            if tokens.get(*position) != Some(&Token::Close) {
+                return Err("expected '}' to close repeat".to_string());
+            }
+            *position += 1;
+            commands.push(Command::Repeat(count, body));
+            continue;
+        }
+
+        if let Some(command) = parse_command(&word, &args)? {
+            commands.push(command);
+        }
+    }
+
+    Ok(commands)
+}
+
+fn parse_command(word: &str, args: &[String]) -> Result<Option<Command>, String> {
+    let single_arg = || match args {
+        [arg] => Ok(arg.clone()),
+        _ => Err(format!("'{}' takes one argument", word)),
+    };
+
+    let command = match word {
+        "load" => Command::Load(single_arg()?),
+        "output-file" => Command::OutputFile(single_arg()?),
+        "compare-to" => Command::CompareTo(single_arg()?),
+        "output-list" => {
+            let columns = args.iter()
+                .map(|a| parse_column(a))
+                .collect::<Result<Vec<_>, _>>()?;
+            Command::OutputList(columns)
+        }
+        "set" => match args {
+            [variable, value] => Command::Set(parse_variable(variable)?, parse_value(value)?),
+            _ => return Err("'set' takes a variable and a value".to_string()),
+        },
+        "ticktock" | "tick" => Command::TickTock,
+        "output" => Command::Output,
+        "echo" => Command::Echo(single_arg()?),
+        // the second half of a clock cycle, already executed by `tick`
+        "tock" => return Ok(None),
+        // only meaningful in the graphical emulator
+        "clear-echo" | "breakpoint" | "clear-breakpoints" => return Ok(None),
+        _ => return Err(format!("unsupported command '{}'", word)),
+    };
+
+    Ok(Some(command))
+}
+
+fn parse_variable(name: &str) -> Result<Variable, String> {
+    let variable = match name {
+        "A" => Variable::A,
+        "D" => Variable::D,
+        "PC" => Variable::PC,
+        "time" => Variable::Time,
+        _ => {
+            let address = name.strip_prefix("RAM[")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|address| address.parse().ok())
+                .ok_or_else(|| format!("unknown variable '{}'", name))?;
+            Variable::Ram(address)
+        }
+    };
+
+    Ok(variable)
+}
+
+// values are decimal unless prefixed with %D, %X or %B
+fn parse_value(value: &str) -> Result<i16, String> {
+    let invalid = || format!("invalid value '{}'", value);
+
+    let parsed = if let Some(hex) = value.strip_prefix("%X") {
+        u16::from_str_radix(hex, 16).map(|v| v as i16).map_err(|_| invalid())?
+    } else if let Some(binary) = value.strip_prefix("%B") {
+        u16::from_str_radix(binary, 2).map(|v| v as i16).map_err(|_| invalid())?
+    } else {
+        let decimal = value.strip_prefix("%D").unwrap_or(value);
+        decimal.parse::<i32>()
+            .ok()
+            .filter(|v| (i16::MIN as i32..=u16::MAX as i32).contains(v))
+            .map(|v| v as i16)
+            .ok_or_else(invalid)?
+    };
+
+    Ok(parsed)
+}
+
+// `RAM[0]%D2.6.2`. Columns without a format are printed as `%D1.6.1`
+fn parse_column(column: &str) -> Result<OutputColumn, String> {
+    let invalid = || format!("invalid output column '{}'", column);
+
+    let (name, format) = match column.split_once('%') {
+        Some((name, format)) => (name, format),
+        None => (column, "D1.6.1"),
+    };
+
+    let mut chars = format.chars();
+    let format_char = chars.next().filter(|c| "DXBS".contains(*c)).ok_or_else(invalid)?;
+    let sizes = chars.as_str()
+        .split('.')
+        .map(|n| n.parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    let [pad_left, len, pad_right] = sizes[..] else {
+        return Err(invalid());
+    };
+
+    Ok(OutputColumn {
+        variable: parse_variable(name)?,
+        name: name.to_string(),
+        format: format_char,
+        pad_left,
+        len,
+        pad_right,
+    })
+}
@@ -0,0 +1,143 @@
+//! Selects the emitter that code is generated with at runtime.
+//!
+//! To add a backend, implement [`EmitAsm`] for it, then add it to [`Backend`], [`Backend::ALL`]
+//! and [`BackendContext`].
+
+use std::io::Write;
+
+use super::compact_emitter::{CEmitterContext, CompactEmitter};
+use super::emit::{EContext, EmitAsm, EmitOptions};
+use super::simple_emitter::{SContext, SimpleEmitter};
+use super::writer::{CodeWriter, WriterContext};
+use super::{CommandDetails, TransformError, TransformResult};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Straightforward code for every command
+    #[default]
+    Simple,
+    /// Smaller code that shares subroutines between commands
+    Compact,
+}
+
+impl Backend {
+    /// Every backend, in the order they are listed to users
+    pub const ALL: &'static [Backend] = &[Backend::Simple, Backend::Compact];
+
+    /// The name the backend is selected by on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Simple => "simple",
+            Backend::Compact => "compact",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Backend> {
+        Backend::ALL.iter().copied().find(|backend| backend.name() == name)
+    }
+
+    /// A fresh context to translate a program with
+    pub fn context(self) -> BackendContext {
+        self.context_with(EmitOptions::default())
+    }
+
+    /// A fresh context to translate a program with, emitting code the given way
+    pub fn context_with(self, options: EmitOptions) -> BackendContext {
+        match self {
+            Backend::Simple => BackendContext::Simple(WriterContext::with_options(options)),
+            Backend::Compact => BackendContext::Compact(WriterContext::with_options(options)),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The state threaded from one file to the next while translating a program with a backend
+#[derive(Clone)]
+pub enum BackendContext {
+    Simple(WriterContext<SContext>),
+    Compact(WriterContext<CEmitterContext>),
+}
+
+impl BackendContext {
+    pub fn backend(&self) -> Backend {
+        match self {
+            BackendContext::Simple(_) => Backend::Simple,
+            BackendContext::Compact(_) => Backend::Compact,
+        }
+    }
+
+    /// The ROM address of the first instruction emitted for every command written so far
+    pub fn command_addresses(&self) -> &[usize] {
+        match self {
+            BackendContext::Simple(context) => context.command_addresses(),
+            BackendContext::Compact(context) => context.command_addresses(),
+        }
+    }
+
+    /// Number of instructions written so far
+    pub fn instruction_count(&self) -> usize {
+        match self {
+            BackendContext::Simple(context) => context.instruction_count(),
+            BackendContext::Compact(context) => context.instruction_count(),
+        }
+    }
+
+    /// Write the commands of one file to `out`.
+    /// The context is only advanced if the whole file was written without errors.
+    pub fn write_file<W: Write>(
+        &mut self,
+        commands: &[(CommandDetails, String)],
+        out: W,
+        emit_init: bool,
+        file_name: &str,
+    ) -> TransformResult<()> {
+        match self {
+            BackendContext::Simple(context) => {
+                write_file::<_, SimpleEmitter<W>, _>(context, commands, out, emit_init, file_name)
+            }
+            BackendContext::Compact(context) => {
+                write_file::<_, CompactEmitter<W>, _>(context, commands, out, emit_init, file_name)
+            }
+        }
+    }
+}
+
+fn write_file<C, E, W>(
+    context: &mut WriterContext<C>,
+    commands: &[(CommandDetails, String)],
+    out: W,
+    emit_init: bool,
+    file_name: &str,
+) -> TransformResult<()>
+    where C: EContext,
+    E: EmitAsm<C, W>,
+    W: Write
+{
+    let mut writer: CodeWriter<C, E, W> =
+        CodeWriter::with_context(context.clone(), out, emit_init, file_name);
+
+    // keep going after an error so that every error in the file is reported
+    let mut errors = Vec::new();
+    for (command, line) in commands {
+        if let Err(error) = writer.write_command(command, line) {
+            errors.push(error);
+        }
+    }
+
+    match writer.close() {
+        Ok(new_context) if errors.is_empty() => {
+            *context = new_context;
+            Ok(())
+        }
+        Ok(_) => Err(TransformError::from_errors(errors)),
+        Err(error) => {
+            errors.push(error);
+            Err(TransformError::from_errors(errors))
+        }
+    }
+}
@@ -1,14 +1,16 @@
 //! An hack assembly emitter that prioritizes small assembly size
 
-use std::fs::File;
 use std::io::BufWriter;
 
 use std::fmt::{Arguments, Write as FmtWrite};
 use std::io::Write as IoWrite;
-use std::sync::Arc;
 use crate::transformer::Segment;
-use hack_macro::{emit_fmt_hack, emit_hack, fmt_hack, hack_str};
-use crate::transformer::emit::{EContext, EmitAsm};
+use hack_macro::{emit_fmt_hack, emit_hack};
+use crate::transformer::emit::{EContext, EmitAsm, EmitOptions, Lowering, SharedCode};
+use crate::transformer::emit::{CALL_PROC, EQ_PROC, GT_PROC, HALT_LABEL, LT_PROC, NEG_PROC, RETURN_PROC};
+use crate::transformer::emit::{LOGIC_FALSE, LOGIC_TRUE};
+use crate::hack::buffer::InstructionBuffer;
+use crate::hack::instruction::Instruction;
 
 struct SymbolGenerator {
     next_id: usize,
@@ -21,7 +23,7 @@ impl SymbolGenerator {
 
     fn next_commented(&mut self, label_start: &str) -> String {
         assert!(
-            label_start.split_whitespace().skip(1).next().is_none(),
+            label_start.split_whitespace().nth(1).is_none(),
             "no whitespace allowed in labels"
         );
         let mut out = String::new();
@@ -30,58 +32,67 @@ impl SymbolGenerator {
 
         self.next_id += 1;
 
-        return out;
+        out
     }
 }
 
 #[derive(Clone)]
 struct FuncEmitter {
-    returns: usize,
     calls: usize,
 }
 
 impl FuncEmitter {
     fn new() -> FuncEmitter {
-        FuncEmitter { returns: 0, calls: 0 }
+        FuncEmitter { calls: 0 }
     }
 
     fn call(&mut self) -> usize {
         let ret = self.calls;
         self.calls+=1;
 
-        return ret;
-    }
-
-    fn ret(&mut self) -> usize {
-        let ret = self.returns;
-        self.returns+=1;
-
-        return ret;
+        ret
     }
 }
 
-pub struct CompactEmitter {
-    writer: BufWriter<Arc<File>>,
+pub struct CompactEmitter<W: IoWrite> {
+    writer: BufWriter<W>,
     symbol_generator: SymbolGenerator,
     emitted_instructions_count: usize,
-    func_emitter: FuncEmitter
+    func_emitter: FuncEmitter,
+    file_name: String,
+    lowering: Lowering,
+    peephole: bool,
+    /// Emitted code that has not been written yet
+    buffer: InstructionBuffer,
 }
 
 
 
-impl EmitAsm<CEmitterContext> for CompactEmitter {
-    fn with_context(context: CEmitterContext, stream: Arc<File>) -> Self {
+impl<W: IoWrite> EmitAsm<CEmitterContext, W> for CompactEmitter<W> {
+    fn with_context(context: CEmitterContext, stream: W) -> Self {
         Self::with_context(context, stream)
     }
 
-    fn new(stream: Arc<File>) -> Self {
+    fn new(stream: W) -> Self {
         Self::new(stream)
     }
 
+    fn instruction_count(&self) -> usize {
+        self.emitted_instructions_count + self.buffer.instruction_count()
+    }
+
+    fn flush(&mut self, addresses: &mut [usize]) {
+        self.flush(addresses)
+    }
+
     fn close(self) -> CEmitterContext {
         self.close()
     }
 
+    fn begin_file(&mut self, file_name: &str) {
+        self.begin_file(file_name);
+    }
+
     fn emit_init(&mut self) {
         self.emit_init();
     }
@@ -213,58 +224,114 @@ impl EmitAsm<CEmitterContext> for CompactEmitter {
     fn not(&mut self) {
         self.not()
     }
+
+    fn add_const(&mut self, val: i16) {
+        SharedCode::add_const(self, val)
+    }
+
+    fn if_not_goto(&mut self, symbol: &str) {
+        SharedCode::if_not_goto(self, symbol)
+    }
+}
+
+impl<W: IoWrite> SharedCode for CompactEmitter<W> {
+    fn emit(&mut self, instruction: Instruction, note: Option<String>) {
+        self.buffer.push(instruction, note);
+    }
+
+    fn next_label(&mut self, label_start: &str) -> String {
+        self.symbol_generator.next_commented(label_start)
+    }
 }
 
 #[derive(Clone)]
 pub struct CEmitterContext {
     emitted_instructions_count: usize,
-    func_emitter: FuncEmitter
+    next_symbol_id: usize,
+    func_emitter: FuncEmitter,
+    lowering: Lowering,
+    peephole: bool,
 }
 
 impl Default for CEmitterContext {
     fn default() -> Self {
         Self {
             emitted_instructions_count: 0,
-            func_emitter: FuncEmitter::new()
+            next_symbol_id: 0,
+            func_emitter: FuncEmitter::new(),
+            lowering: Lowering::default(),
+            peephole: false,
         }
     }
 }
 
-impl EContext for CEmitterContext {}
-const LOGIC_TRUE: i16 = -1;
-const LOGIC_FALSE: i16 = 0;
+impl EContext for CEmitterContext {
+    fn with_options(options: EmitOptions) -> Self {
+        Self {
+            lowering: options.lowering,
+            peephole: options.peephole,
+            ..Self::default()
+        }
+    }
+
+    fn instruction_count(&self) -> usize {
+        self.emitted_instructions_count
+    }
+}
 
-impl CompactEmitter {
-    pub fn close(self) -> CEmitterContext {
-        return CEmitterContext {
+impl<W: IoWrite> CompactEmitter<W> {
+    pub fn close(mut self) -> CEmitterContext {
+        self.flush(&mut []);
+        CEmitterContext {
             emitted_instructions_count: self.emitted_instructions_count,
+            next_symbol_id: self.symbol_generator.next_id,
             func_emitter: self.func_emitter,
-        };
+            lowering: self.lowering,
+            peephole: self.peephole,
+        }
     }
 
-    pub fn new(stream: Arc<File>) -> Self {
+    pub fn new(stream: W) -> Self {
         Self {
             writer: BufWriter::new(stream),
             symbol_generator: SymbolGenerator::new(),
             emitted_instructions_count: 0,
-            func_emitter: FuncEmitter::new()
+            func_emitter: FuncEmitter::new(),
+            file_name: String::new(),
+            lowering: Lowering::default(),
+            peephole: false,
+            buffer: InstructionBuffer::new(),
         }
     }
 
-    pub fn with_context(emitter_context: CEmitterContext, stream: Arc<File>) -> Self {
+    pub fn with_context(emitter_context: CEmitterContext, stream: W) -> Self {
         Self {
             writer: BufWriter::new(stream),
-            symbol_generator: SymbolGenerator::new(),
+            symbol_generator: SymbolGenerator { next_id: emitter_context.next_symbol_id },
             emitted_instructions_count: emitter_context.emitted_instructions_count,
-            func_emitter: FuncEmitter::new()
+            func_emitter: emitter_context.func_emitter,
+            file_name: String::new(),
+            lowering: emitter_context.lowering,
+            peephole: emitter_context.peephole,
+            buffer: InstructionBuffer::new(),
         }
     }
 
+    pub fn begin_file(&mut self, file_name: &str) {
+        self.file_name = file_name.to_string();
+    }
+
     pub fn comment(&mut self, args: std::fmt::Arguments) -> std::io::Result<()> {
-        self.write_fmt(args)
+        self.emit(Instruction::Comment(args.to_string()), None);
+        Ok(())
     }
-    fn write_fmt(&mut self, args: std::fmt::Arguments) -> std::io::Result<()> {
-        self.writer.write_fmt(args)
+
+    // write out the buffered code, see `EmitAsm::flush`
+    pub fn flush(&mut self, addresses: &mut [usize]) {
+        let written = self.buffer
+            .flush(&mut self.writer, self.emitted_instructions_count, self.peephole, addresses)
+            .expect("Io error");
+        self.emitted_instructions_count += written;
     }
 
     pub fn prelude(&mut self) {
@@ -275,7 +342,7 @@ impl CompactEmitter {
             0;JMP
         ");
 
-        self.emit_label_start("neg_proc");
+        self.emit_label_start(NEG_PROC);
         emit_fmt_hack!(r"
             // expects a return address passed in D
             @R15
@@ -291,84 +358,127 @@ impl CompactEmitter {
             0;JMP   // return
         ");
 
+        self.call_proc();
+        self.return_proc();
+        if self.lowering == Lowering::Shared {
+            self.comparison_procs();
+        }
+
         self.emit_label_start(end.as_str());
     }
 
-    pub fn emit_init(&mut self) {
+    // shared body of every call. expects nArgs in R13, the callee in R14 and the return address in D
+    fn call_proc(&mut self) {
+        self.emit_label_start(CALL_PROC);
+        self.d_to_stack();  // push return address
+        for segment in ["LCL", "ARG", "THIS", "THAT"] {
+            emit_fmt_hack!(r"
+                @{segment}
+                D=M
+            ");
+            self.d_to_stack();
+        }
         emit_hack! {r"
-            @Sys.init
-            0;JMP
+            // ARG = SP - 5 - nArgs
+            @R13
+            D=M
+            @5
+            D=D+A
+            @SP
+            D=M-D
+            @ARG
+            M=D
 
-            // end of program - halt
-            @_L_DEADLOOP
-            (_L_DEADLOOP)
-            0;JMP
+            // LCL = SP
+            @SP
+            D=M
+            @LCL
+            M=D
 
+            @R14
+            A=M
+            0;JMP   // jump to callee
         "};
-        self.emitln("");
-
-        // // initalize segments
-        // // 1. clear the memory if needed
-        //
-        // let asm: &str= hack_str!{r"
-        //     // // emit_init: write 256 to stack pointer
-        //     // @256
-        //     // D=A
-        //     // @0
-        //     // M=D // todo: LCL, ARG, THIS, THAT"};
-        //
-        // // 2. setup segment pointers
-        // self.emitln(asm);
-        // self.emitln("");
-        // self.writer.write_fmt(format_args!("{}\n\n", asm))
-        //     .unwrap();
-
-        // panic!("{}", a);
     }
 
-    fn emitln(&mut self, str: &str) {
-        let lines = str.split('\n');
-        for line in lines {
-            let line = line.trim();
+    // shared body of every return
+    fn return_proc(&mut self) {
+        self.emit_label_start(RETURN_PROC);
+        emit_hack! {r"
+            // R13 = frame = LCL
+            @LCL
+            D=M
+            @R13
+            M=D
 
-            // if line is instruction, display the instruction number for debugging convenience
-            if !line.starts_with("//") && !line.starts_with("(") && !line.is_empty() {
-                let no = self.emitted_instructions_count;
-                self.write_fmt(format_args!("{:90}//{:3}\n", line, no))
-                    .unwrap();
-                self.emitted_instructions_count += 1;
-            } else {
-                self.write_fmt(format_args!("{:90}\n", line)).unwrap();
-            }
-        }
-    }
+            // R14 = return address = *(frame - 5)
+            @5
+            A=D-A
+            D=M
+            @R14
+            M=D
 
-    fn emit_label_start(&mut self, symbol: &str) {
-        emit_fmt_hack!("({})", symbol);
-    }
+            // *ARG = return value
+            @SP
+            AM=M-1
+            D=M
+            @ARG
+            A=M
+            M=D
 
+            // SP = ARG + 1
+            D=A+1
+            @SP
+            M=D
+        "};
+
+        // restore the caller's segment pointers from the frame, THAT first
+        for segment in ["THAT", "THIS", "ARG", "LCL"] {
+            emit_fmt_hack!(r"
+                @R13
+                AM=M-1
+                D=M
+                @{segment}
+                M=D
+            ");
+        }
 
-    // puts the last item into register A
-    // clobbers A,D
-    // tested
-    fn stack_to_a(&mut self) {
         emit_hack! {r"
-            @SP
-            M=M-1       // Decrement stack pointer
-            A=M         // A = Stack pointer
-            A=M         // D = old top of stack
+            @R14
+            A=M
+            0;JMP   // jump to return address
         "};
     }
 
-    // tested
-    // clobbers, A, D
-    fn stack_to_d(&mut self) {
+    pub fn emit_init(&mut self) {
         emit_hack! {r"
+            @256
+            D=A
             @SP
-            M=M-1       // Decrement stack pointer
-            A=M         // A = Stack pointer
-            D=M         // D = old top of stack
+            M=D         // initialise stack pointer
+
+            // initialize segment pointers to a known value, LCL = -1 .. THAT = -4
+            @LCL
+            M=-1
+            D=M-1
+            @ARG
+            M=D
+            D=D-1
+            @THIS
+            M=D
+            D=D-1
+            @THAT
+            M=D
         "};
+
+        self.call(0, "Sys.init");
+
+        emit_fmt_hack!(r"
+            // end of program - halt
+            @{HALT_LABEL}
+            ({HALT_LABEL})
+            0;JMP
+        ");
     }
 
     // push the item in register A onto the stack
@@ -391,30 +501,6 @@ impl CompactEmitter {
         "};
     }
 
-    fn emit_push_local_a(&mut self) {
-        emit_hack! {r"
-            // emit_push_a
-            D=A
-            @LCL
-            A=M     // A=*LCL
-            M=D     // **LCL = D
-            @SP
-            M=M+1   //*LCL = *LCL+1
-        "};
-    }
-
-    fn assign_a(&mut self, value: i16) {
-        emit_fmt_hack!(r"
-            @{0} // A = {0}
-        ", value);
-    }
-
-    fn assign_a_str(&mut self, value: &str) {
-        emit_fmt_hack!(r"
-            @{0}    // A = {0}
-        ", value);
-    }
-
     // tested
     pub fn push_const(&mut self, val: i16) {
         // self.assign_a(val);
@@ -427,16 +513,15 @@ impl CompactEmitter {
         // "};
         // self.emitln("");
 
-        emit_fmt_hack!(r"
-            @{val}
+        self.assign_a(val);
+        emit_hack! {r"
             D=A
             @SP
             A=M
             M=D
             @SP
             M=M+1
-        ");
-        self.emitln("");
+        "};
     }
 
     // tested
@@ -453,13 +538,17 @@ impl CompactEmitter {
             A=D         // A = result
         "};
         self.a_to_stack();
-        self.emitln("");
     }
 
 
 
     // seems to check out
     pub fn eq(&mut self) {
+        if self.lowering == Lowering::Shared {
+            self.call_comparison(EQ_PROC);
+            return;
+        }
+
         let is_equal = self.symbol_generator.next_commented("is_eq");
         let not_equal = self.symbol_generator.next_commented("not_equal");
         let end = self.symbol_generator.next_commented("end");
@@ -484,7 +573,6 @@ impl CompactEmitter {
             A=M-1       // grab pointer to top item in stack
             M=D         // write to stack
         "};
-        self.emitln("");
     }
 
     // tested!
@@ -500,11 +588,15 @@ impl CompactEmitter {
             M=D         // write result on stack"
         };
 
-        self.emitln("");
     }
 
     // tested!
     pub fn lt(&mut self) {
+        if self.lowering == Lowering::Shared {
+            self.call_comparison(LT_PROC);
+            return;
+        }
+
         let is_lt = self.symbol_generator.next_commented("is_lt");
         let is_not_lt = self.symbol_generator.next_commented("is_not_lt");
         let end = self.symbol_generator.next_commented("end");
@@ -530,11 +622,15 @@ impl CompactEmitter {
             M=D      // write value to stack
         ");
 
-        self.emitln("");
     }
 
     // tested
     pub fn gt(&mut self) {
+        if self.lowering == Lowering::Shared {
+            self.call_comparison(GT_PROC);
+            return;
+        }
+
         let is_gt = self.symbol_generator.next_commented("is_gt");
         let is_not_gt = self.symbol_generator.next_commented("is_not_gt");
         let end = self.symbol_generator.next_commented("end");
@@ -558,7 +654,6 @@ impl CompactEmitter {
             A=M-1   // address of top item in stack
             M=D     // write result to top of stack
         ");
-        self.emitln("");
     }
 
     // tested
@@ -567,7 +662,7 @@ impl CompactEmitter {
         emit_fmt_hack!(r"
             @{return_label}
             D=A
-            @neg_proc
+            @{NEG_PROC}
             0;JMP
         ");
         self.emit_label_start(return_label.as_str());
@@ -590,7 +685,6 @@ impl CompactEmitter {
             A=M-1       // pointer to last item on stack
             M=M|D       // write result to stack
         "};
-        self.emitln("");
     }
 
     // tested
@@ -603,7 +697,6 @@ impl CompactEmitter {
             D=!D        // calculate
             M=D         // write result to stack
         "};
-        self.emitln("");
     }
 
     pub fn and(&mut self) {
@@ -614,22 +707,23 @@ impl CompactEmitter {
             A=M-1       // A = 2nd item from stack
             M=M&D       // write result to stack, overwriting 2nd item
         "};
-        self.emitln("");
     }
 
     fn segment_symbol_str(&self, segment: Segment, _offset: i16) -> &str {
-        return match segment {
+        match segment {
             Segment::Local => "LCL",
             Segment::Constant => {
                 unreachable!("Constant is not a real segment")
             }
             Segment::Argument => "ARG",
             Segment::Temp => "TMP",
-            Segment::Static => "STATIC",
+            Segment::Static => {
+                unreachable!("Static is addressed through per-file symbols")
+            }
             Segment::That => "THAT",
             Segment::This => "THIS",
             Segment::Pointer => "THIS",
-        };
+        }
     }
 
     // move the value at offset n from the segment onto the stack
@@ -685,7 +779,6 @@ impl CompactEmitter {
             M=M-1       // Decrease stack pointer
         "};
 
-        self.emitln("");
     }
 
     // move the value from the stack to the segment at offset n
@@ -724,7 +817,6 @@ impl CompactEmitter {
             A=M-1   // A = value at top of stack
             M=D     // write value to stack
         "};
-        self.emitln("");
     }
 
     // take the argument at offset n and place it on the stack
@@ -762,8 +854,21 @@ impl CompactEmitter {
         self.push_non_stack_segment(Segment::Temp, n);
     }
 
+    // statics are mangled per file as `FileName.n` so that each file gets its own variables
+    fn static_symbol(&self, n: i16) -> String {
+        format!("{}.{}", self.file_name, n)
+    }
+
     pub fn push_static_n(&mut self, n: i16) {
-        self.push_non_stack_segment(Segment::Static, n);
+        let symbol = self.static_symbol(n);
+        emit_fmt_hack!(r"
+            @{symbol}
+            D=M     // D = value of static
+            @SP
+            M=M+1   // increase stack pointer
+            A=M-1   // A = top of stack
+            M=D     // write value to stack
+        ");
     }
 
     pub fn push_this_n(&mut self, n: i16) {
@@ -775,7 +880,12 @@ impl CompactEmitter {
     }
 
     pub fn pop_static_n(&mut self, n: i16) {
-        self.pop_non_stack_segment(Segment::Static, n);
+        let symbol = self.static_symbol(n);
+        self.stack_to_d();
+        emit_fmt_hack!(r"
+            @{symbol}
+            M=D     // write value to static
+        ");
     }
 
     pub fn pop_local_n(&mut self, n: i16) {
@@ -786,27 +896,25 @@ impl CompactEmitter {
         self.pop_non_stack_segment(Segment::Pointer, n);
     }
 
-    const USER_LABEL_PREFIX: &'static str = "user_";
+    // declare the start of a label. the symbol is already mangled by the writer
     pub fn label(&mut self, symbol: &str) {
-        emit_fmt_hack!{r"({}{})", Self::USER_LABEL_PREFIX, symbol};
+        self.emit_label_start(symbol);
     }
 
     // jump to the symbol if stack top > 0
     pub fn ifgoto(&mut self, symbol: &str) {
         self.stack_to_d();
-        emit_fmt_hack! {r"@{}{}", Self::USER_LABEL_PREFIX, symbol};
+        emit_fmt_hack! {r"@{}", symbol};
         emit_hack! {r"
             D;JNE
         "};
-        self.emitln("");
     }
 
     pub fn goto(&mut self, symbol: &str) {
-        emit_fmt_hack! {r"@{}{}", Self::USER_LABEL_PREFIX, symbol};
+        emit_fmt_hack! {r"@{}", symbol};
         emit_hack! {r"
             0;JMP
         "};
-        self.emitln("");
     }
 
     // a function declaration
@@ -815,217 +923,76 @@ impl CompactEmitter {
         self.emit_label_start(symbol);
 
         if n_vars > 0 {
+            // zero the locals
             emit_hack! {r"
-                @LCL
+                @SP
                 A=M
-                D=M
-                @R14
-                M=D
+                M=0
             "};
-
-            // zero the locals
-            for _ in 0..n_vars {
+            for _ in 1..n_vars {
                 emit_hack! {r"
-                    @R14
+                    A=A+1
                     M=0
-
-                    @R14
-                    M=M+1
                 "};
             }
+            emit_hack! {r"
+                D=A+1
+                @SP
+                M=D     // SP = past the last local
+            "};
         }
     }
 
-
-
     // tested
     pub fn _return(&mut self) {
-        // move return value to args[0] of caller
-        // &args[0] = return
-        emit_hack! {r"
-            // return
-            // insert return value to arg[0] of caller
-                // load caller's argument segment ARG= *LCL-3
-                @LCL
-                A=M     // A = address of first local
-                A=M
-                A=A-1
-                A=A-1
-                A=A-1   // A = address of caller's ARG
-                A=M
-                D=M     // A = caller's ARG
-                @ARG
-                A=M
-                M=D     // write segment value
-
-                // pop item off stack
-                @SP
-                M=M-1       // Decrement stack pointer
-                A=M         // A = Stack pointer
-                D=M         // D = old top of stack
-                // write item to arg0
-                @ARG
-                A=M         // address of first argument
-                M=D         // arg0 = item 1
-
-            // discard stack
-            @ARG
-            D=M+1           // end of caller's stackframe
-            @SP
-            M=D             // set stack pointer to just underneath previous function stackframe
-
-            // stow return address address
-            @LCL
-            A=M
-            A=A-1
-            A=A-1
-            A=A-1
-            A=A-1
-            A=A-1   // D = address of return address
-            D=A     // D = address of return address
-            @R13
-            M=D     // mem[13] = return address address
-
-            // recover caller's segment pointers
-                // load caller's THAT segment THAT = *LCL-1
-                @LCL
-                A=M     // A = address of first local
-                A=A-1   // A = address of caller's THAT
-                D=M     // D = caller's semgnet value
-                @THAT
-                M=D
-
-                // load caller's THIS segment THIS = *LCL-2
-                @LCL
-                A=M     // A = address of first local
-                A=A-1   // A = address of caller's THAT
-                A=A-1   // A = address of caller's THIS
-                D=M     // D = caller's semgnet value
-                @THIS
-                M=D
-
-                // load caller's ARG segment ARG = *LCL-3
-                @LCL
-                A=M     // A = address of first local
-                A=A-1   // A = address of caller's THAT
-                A=A-1   // A = address of caller's THIS
-                A=A-1   // A = address of caller's ARG
-                D=M     // D = caller's semgnet value
-                @ARG
-                M=D
-
-                // load caller's LCL segment
-                @LCL
-                A=M     // A = address of first local
-                A=A-1   // A = address of caller's THAT
-                A=A-1   // A = address of caller's THIS
-                A=A-1   // A = address of caller's ARG
-                A=A-1   // A = address of caller's LCL
-                D=M     // D = caller's semgnet value
-                @LCL
-                M=D
-
-            // jump to return address
-            @R13
-            A=M
-            A=M         // A = return address
-            0;JMP       // jump to return address
-        "};
-        self.emitln("");
+        emit_fmt_hack!(r"
+            @{RETURN_PROC}
+            0;JMP
+        ");
     }
 
-    fn segment_pointer_to_stack(&mut self, segment: Segment) {
-        // let not_temp_segment;
-        let segment_symbol = self.segment_symbol_str(segment, -1);
+    pub fn call(&mut self, n_args: i16, callee_symbol: &str) {
+        let caller_return = format!("{}$ret.{}", callee_symbol, self.func_emitter.call());
 
-        // D = address of segment start
-        match segment {
-            Segment::Pointer => {
-                emit_fmt_hack!("@{}", segment_symbol);
-                emit_hack! {r"
-                    D=A         // D = segment start
-                "};
+        // R13 = nArgs
+        match n_args {
+            0 => {
+                emit_hack!(r"
+                    @R13
+                    M=0
+                ");
+            }
+            1 => {
+                emit_hack!(r"
+                    @R13
+                    M=1
+                ");
             }
-            Segment::Temp => {panic!("temp has no segment pointer"); }
             _ => {
-                emit_fmt_hack!("@{}", segment_symbol);
-                emit_hack! {r"
-                    D=M         // D = segment start
-                "};
+                emit_fmt_hack!(r"
+                    @{n_args}
+                    D=A
+                    @R13
+                    M=D
+                ");
             }
         }
 
-        // A = segment offset
-        emit_hack! {r"
-            @SP
-            A=M         // A = stack pointer
-            M=D         // write segment pointer to stack
-            @SP
-            M=M+1       // increase stack pointer
-        "};
-    }
-
-    pub fn call(&mut self, n_args: i16, callee_symbol: &str) {
-        let caller_return = fmt_hack!("{}$ret.{}", callee_symbol, self.func_emitter.call());
-
-        // set Ram[13] to address of first argument
-        emit_hack! {r"
-            // save stack address of first argument
-            @SP
-            D=M
-        "};
-        self.assign_a(n_args);
-        emit_hack! {r"
-            D=D-A
-            @R13
-            M=D
-        "};
-
-        emit_hack! {r"// save a return address"};
-        emit_fmt_hack!("@{}", caller_return.as_str());
-        emit_hack! {r"
+        emit_fmt_hack!(r"
+            // R14 = callee
+            @{callee_symbol}
             D=A
-            @SP
-            A=M
+            @R14
             M=D
-            @SP
-            M=M+1
-        "};
-
-        // save caller stackframe
-        self.segment_pointer_to_stack(Segment::Local);
-        self.segment_pointer_to_stack(Segment::Argument);
-        self.segment_pointer_to_stack(Segment::This);
-        self.segment_pointer_to_stack(Segment::That);
-
 
-        emit_hack!{r"
-            // setup segment pointers for callee
-                // ARG = address of first argument passed
-                @R13
-                D=M
-                @ARG
-                M=D
-
-                // LCL = start of caller's stackframe
-                @SP
-                D=M
-                @LCL
-                A=M
-                M=D
-            // jump
-
-        "};
-
-        // jump
-        emit_fmt_hack!("@{}", callee_symbol);
-        emit_hack! {r"
+            @{caller_return}
+            D=A     // D = return address
+            @{CALL_PROC}
             0;JMP
-        "};
+        ");
 
         // declare callee return address
         self.emit_label_start(caller_return.as_str());
 
-        self.emitln("");
     }
 }
@@ -0,0 +1,123 @@
+//! Errors and warnings located in a source file, rendered with a snippet of the offending line
+
+use std::ops::Range;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Where in the source the problem is. None for problems with the program as a whole
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column of the start of the span, counted in characters
+    pub column: usize,
+    /// Byte offsets of the offending text within the whole file
+    pub span: Range<usize>,
+    /// The full text of the line the span starts on
+    pub source_line: String,
+}
+
+impl Diagnostic {
+    /// Locate `span` within `source` and build a diagnostic for it
+    pub fn new(severity: Severity, message: String, path: PathBuf, source: &str, span: Range<usize>) -> Diagnostic {
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[start..].find('\n').map(|i| start + i).unwrap_or(source.len());
+
+        let line = source[..line_start].matches('\n').count() + 1;
+        let source_line = &source[line_start..line_end];
+
+        let location = Location {
+            path,
+            line,
+            column: source[line_start..start].chars().count() + 1,
+            span,
+            source_line: source_line.trim_end_matches('\r').to_string(),
+        };
+
+        Diagnostic {
+            severity,
+            message,
+            location: Some(location),
+        }
+    }
+
+    /// A diagnostic about the program as a whole rather than a place in it
+    pub fn unlocated(severity: Severity, message: String) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message,
+            location: None,
+        }
+    }
+
+    pub fn error(message: String, path: PathBuf, source: &str, span: Range<usize>) -> Diagnostic {
+        Self::new(Severity::Error, message, path, source, span)
+    }
+
+    pub fn warning(message: String, path: PathBuf, source: &str, span: Range<usize>) -> Diagnostic {
+        Self::new(Severity::Warning, message, path, source, span)
+    }
+
+    /// Render in the style of rustc, with just the first line if there is no location:
+    ///
+    /// ```text
+    /// error: unknown command 'ad'
+    ///  --> Foo.vm:3:1
+    ///   |
+    /// 3 | ad
+    ///   | ^^
+    /// ```
+    pub fn render(&self) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let location = match &self.location {
+            Some(location) => location,
+            None => return format!("{}: {}", severity, self.message),
+        };
+        let number = location.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        // the caret covers the span, but stops at the end of the line
+        let line_len = location.source_line.chars().count();
+        let caret_start = (location.column - 1).min(line_len);
+        let caret_len = location.span.len().min(line_len - caret_start).max(1);
+
+        format!(
+            "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            severity,
+            self.message,
+            gutter,
+            location.path.display(),
+            location.line,
+            location.column,
+            gutter,
+            number,
+            location.source_line,
+            gutter,
+            " ".repeat(caret_start),
+            "^".repeat(caret_len)
+        )
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.render())
+    }
+}
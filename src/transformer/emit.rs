@@ -1,26 +1,40 @@
-use std::fs::File;
-use std::sync::Arc;
+use std::io::Write;
 
-/// Specifies a type that is able to emit hack assembly instructions.
-pub trait EmitAsm<C> {
+use hack_macro::{emit_fmt_hack, emit_hack};
+use crate::hack::instruction::{comp, Instruction, JEQ, JGT, JLT};
+
+/// Specifies a type that is able to emit hack assembly instructions to a sink `W`.
+pub trait EmitAsm<C, W: Write> {
     /// Re-construct an emitter with any previous context.
-    fn with_context(context: C, stream: Arc<File>) -> Self where Self: Sized {
+    fn with_context(_context: C, stream: W) -> Self where Self: Sized {
         Self::new(stream)
     }
 
     /// Create a new emitter with default configuration.
-    fn new(stream: Arc<File>) -> Self;
+    fn new(stream: W) -> Self;
+
+    /// Number of instructions emitted so far, which is also the ROM address of the next instruction.
+    fn instruction_count(&self) -> usize;
+
+    /// Write out any instructions the emitter is still holding on to. Any of `addresses` that were
+    /// handed out by [`EmitAsm::instruction_count`] since the last flush are moved to where their
+    /// instruction ended up, in case instructions were left out.
+    fn flush(&mut self, _addresses: &mut [usize]) {}
 
     /// Finalize work of emitter and snapshot the internal state.
     fn close(self) -> C;
 
+    /// Begin emitting code translated from the named source file.
+    /// The name is the file stem, e.g. `Foo` for `Foo.vm`, and is used to mangle static symbols.
+    fn begin_file(&mut self, file_name: &str);
+
     /// If bootstrapping is requested by user, this function does it.
     fn emit_init(&mut self);
 
     /// For any always required initialization.
     fn prelude(&mut self);
 
-    /// Insert in a comment into the generated code.
+    /// Insert a comment into the generated code, given without the leading `//`.
     fn comment(&mut self, args: std::fmt::Arguments) -> std::io::Result<()>;
 
     /// Push the value given onto the stack.
@@ -56,8 +70,202 @@ pub trait EmitAsm<C> {
     fn ifgoto(&mut self, symbol: &str);
     fn not(&mut self);
 
+    /// Add a constant, which may be negative, to the top of the stack.
+    fn add_const(&mut self, val: i16) {
+        self.push_const(val);
+        self.add();
+    }
+
+    /// Pop a value and jump to the symbol unless it is true (-1).
+    fn if_not_goto(&mut self, symbol: &str) {
+        self.not();
+        self.ifgoto(symbol);
+    }
+}
+
+/// Code both emitters generate the same way. An emitter only says where its instructions go and
+/// how its labels are made unique.
+pub(crate) trait SharedCode {
+    fn emit(&mut self, instruction: Instruction, note: Option<String>);
+
+    /// A label no other code uses, starting with `label_start`.
+    fn next_label(&mut self, label_start: &str) -> String;
+
+    fn emit_label_start(&mut self, symbol: &str) {
+        self.emit(Instruction::Label(symbol.to_string()), None);
+    }
+
+    // shared body of a comparison, which replaces the top two items of the stack with the result.
+    // expects a return address passed in D
+    fn comparison_proc(&mut self, symbol: &str, jump: u16) {
+        self.emit_label_start(symbol);
+        emit_fmt_hack!(r"
+            @R15
+            M=D         // stow return address
+            @SP
+            AM=M-1      // decrement stack pointer
+            D=M         // D = pop1
+            A=A-1
+            D=M-D       // D = pop2 - pop1
+            M={LOGIC_TRUE}
+            @{symbol}_end
+            D;{jump}    // keep true if the comparison holds
+            @SP
+            A=M-1
+            M={LOGIC_FALSE}
+            ({symbol}_end)
+            @R15
+            A=M
+            0;JMP       // return
+        ");
+    }
+
+    fn comparison_procs(&mut self) {
+        self.comparison_proc(EQ_PROC, JEQ);
+        self.comparison_proc(GT_PROC, JGT);
+        self.comparison_proc(LT_PROC, JLT);
+    }
+
+    fn call_comparison(&mut self, symbol: &str) {
+        let return_label = self.next_label("cmp_ret");
+        emit_fmt_hack!(r"
+            @{return_label}
+            D=A
+            @{symbol}
+            0;JMP
+        ");
+        self.emit_label_start(return_label.as_str());
+    }
+
+    // clobbers, A, D
+    fn stack_to_d(&mut self) {
+        emit_hack! {r"
+            @SP
+            M=M-1       // Decrement stack pointer
+            A=M         // A = Stack pointer
+            D=M         // D = old top of stack
+        "};
+    }
+
+    fn assign_a(&mut self, value: i16) {
+        // an A-instruction only holds 0..=32767, so a negative value is made from its complement
+        if value < 0 {
+            emit_fmt_hack!(r"
+                @{0}
+                A=!A    // A = {1}
+            ", !value, value);
+            return;
+        }
+
+        emit_fmt_hack!(r"
+            @{0} // A = {0}
+        ", value);
+    }
+
+    // jump to the symbol unless stack top is true
+    fn if_not_goto(&mut self, symbol: &str) {
+        self.stack_to_d();
+        emit_fmt_hack!(r"
+            @{symbol}
+            D+1;JNE     // true is -1
+        ");
+    }
+
+    fn add_const(&mut self, val: i16) {
+        match val {
+            1 => {
+                emit_hack! {r"
+                    @SP
+                    A=M-1
+                    M=M+1
+                "};
+            }
+            -1 => {
+                emit_hack! {r"
+                    @SP
+                    A=M-1
+                    M=M-1
+                "};
+            }
+            _ => {
+                self.assign_a(val);
+                emit_hack! {r"
+                    D=A
+                    @SP
+                    A=M-1
+                    M=M+D   // add to top of stack
+                "};
+            }
+        }
+    }
 }
 
+// the computations of the results of a comparison, true being all bits set
+pub(crate) const LOGIC_TRUE: u16 = comp("-1");
+pub(crate) const LOGIC_FALSE: u16 = comp("0");
+
 pub trait EContext : Default + Sized + Clone{
+    /// A fresh context that emits code the given way.
+    fn with_options(options: EmitOptions) -> Self;
+
+    /// Number of instructions emitted before the context was snapshot.
+    fn instruction_count(&self) -> usize;
+}
+
+/// How an emitter generates code
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EmitOptions {
+    pub lowering: Lowering,
+    /// Leave out instructions that have no effect before the code is written.
+    /// Code may then move between commands, so a command no longer starts at a clean state
+    pub peephole: bool,
+}
+
+/// How the commands that need branches, like the comparisons, are lowered to instructions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Lowering {
+    /// Every command gets its own copy of the code, which is fastest
+    #[default]
+    Inline,
+    /// Commands call subroutines shared through the prelude, which is smallest
+    Shared,
+}
+
+impl Lowering {
+    pub const ALL: &'static [Lowering] = &[Lowering::Inline, Lowering::Shared];
+
+    /// The name the lowering is selected by on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Lowering::Inline => "inline",
+            Lowering::Shared => "shared",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lowering> {
+        Lowering::ALL.iter().copied().find(|lowering| lowering.name() == name)
+    }
+}
+
+impl std::fmt::Display for Lowering {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+// shared comparison subroutines in the prelude, used when lowering is `Shared`
+pub const EQ_PROC: &str = "$$eq";
+pub const GT_PROC: &str = "$$gt";
+pub const LT_PROC: &str = "$$lt";
+
+// shared subroutines in the compact prelude that every call and return jumps to
+pub const CALL_PROC: &str = "$$call";
+pub const RETURN_PROC: &str = "$$return";
+
+pub const NEG_PROC: &str = "neg_proc";
+
+/// The label the bootstrap code halts at after `Sys.init` returns
+pub const HALT_LABEL: &str = "_L_DEADLOOP";
 
-}
\ No newline at end of file
+/// Labels the emitters declare outside of any function. A VM function of the same name would
+/// be declared twice
+pub const RESERVED_SYMBOLS: &[&str] = &[EQ_PROC, GT_PROC, LT_PROC, CALL_PROC, RETURN_PROC, NEG_PROC, HALT_LABEL];
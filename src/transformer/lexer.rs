@@ -0,0 +1,125 @@
+//! Splits VM source into tokens, each carrying the byte span it was read from
+
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keyword {
+    Push,
+    Pop,
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+    Label,
+    Goto,
+    IfGoto,
+    Function,
+    Call,
+    Return,
+}
+
+impl Keyword {
+    fn from_str(word: &str) -> Option<Keyword> {
+        let keyword = match word {
+            "push" => Keyword::Push,
+            "pop" => Keyword::Pop,
+            "add" => Keyword::Add,
+            "sub" => Keyword::Sub,
+            "neg" => Keyword::Neg,
+            "eq" => Keyword::Eq,
+            "gt" => Keyword::Gt,
+            "lt" => Keyword::Lt,
+            "and" => Keyword::And,
+            "or" => Keyword::Or,
+            "not" => Keyword::Not,
+            "label" => Keyword::Label,
+            "goto" => Keyword::Goto,
+            "if-goto" => Keyword::IfGoto,
+            "function" => Keyword::Function,
+            "call" => Keyword::Call,
+            "return" => Keyword::Return,
+            _ => return None,
+        };
+
+        Some(keyword)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    /// Any other word, such as a segment, function or label name
+    Identifier,
+    /// Digits with an optional leading `-`
+    Integer,
+    /// A `//` comment running to the end of the line
+    Comment,
+    Newline,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// Byte offsets of the token in the source
+    pub span: Range<usize>,
+}
+
+impl Token {
+    /// The text of the token
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.span.clone()]
+    }
+}
+
+/// Split the whole source into tokens. Whitespace other than newlines is dropped.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let bytes = source.as_bytes();
+    let mut i = 0;
+
+    while i < source.len() {
+        let rest = &source[i..];
+        let c = rest.chars().next().unwrap();
+
+        if c == '\n' {
+            tokens.push(Token { kind: TokenKind::Newline, span: i..i + 1 });
+            i += 1;
+        } else if c.is_whitespace() {
+            i += c.len_utf8();
+        } else if rest.starts_with("//") {
+            let end = rest.find('\n').map(|n| i + n).unwrap_or(source.len());
+            tokens.push(Token { kind: TokenKind::Comment, span: i..end });
+            i = end;
+        } else {
+            // a word runs until whitespace or the start of a comment
+            let mut end = i;
+            while end < source.len() {
+                let c = source[end..].chars().next().unwrap();
+                if c.is_whitespace() || (c == '/' && bytes.get(end + 1) == Some(&b'/')) {
+                    break;
+                }
+                end += c.len_utf8();
+            }
+
+            let word = &source[i..end];
+            let digits = word.strip_prefix('-').unwrap_or(word);
+            let kind = if let Some(keyword) = Keyword::from_str(word) {
+                TokenKind::Keyword(keyword)
+            } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                TokenKind::Integer
+            } else {
+                TokenKind::Identifier
+            };
+
+            tokens.push(Token { kind, span: i..end });
+            i = end;
+        }
+    }
+
+    tokens
+}
@@ -1,11 +1,19 @@
-mod simple_emitter;
+pub mod simple_emitter;
 mod parser;
-pub(crate) mod writer;
-pub(crate) mod transform;
-mod emit;
-mod compact_emitter;
+mod lexer;
+pub mod validate;
+pub mod writer;
+pub mod transform;
+pub mod emit;
+pub mod diagnostic;
+pub mod compact_emitter;
+pub mod backend;
 
-pub(crate) use writer::WriterContext;
-pub(crate) use parser::Segment;
-pub(crate) use transform::TransformError;
-pub(crate) use transform::TransformResult;
+pub use writer::WriterContext;
+pub use backend::{Backend, BackendContext};
+pub use emit::{EmitOptions, Lowering};
+pub use parser::Segment;
+pub use parser::{ArithmeticType, CommandDetails, Parser};
+pub use validate::Validator;
+pub use transform::TransformError;
+pub use transform::TransformResult;
@@ -1,54 +1,18 @@
 use crate::transformer::TransformError;
 use crate::transformer::TransformResult;
+use crate::transformer::diagnostic::{Diagnostic, Severity};
+use crate::transformer::lexer::{self, Keyword, Token, TokenKind};
 use std::io::Read;
-
-// inspired by https://depth-first.com/articles/2021/12/16/a-beginners-guide-to-parsing-in-rust/
-struct Scanner {
-    cursor: usize,
-    characters: Vec<char>,
-}
-
-impl Scanner {
-    fn new(characters: Vec<char>) -> Scanner {
-        Scanner {
-            characters,
-            cursor: 0,
-        }
-    }
-
-    pub fn peek(&self) -> Option<&char> {
-        self.characters.get(self.cursor)
-    }
-
-    pub fn peek_n(&self, len: usize) -> Option<&char> {
-        self.characters.get(self.cursor + len)
-    }
-
-    pub fn pop(&mut self) -> Option<&char> {
-        let current = self.characters.get(self.cursor);
-        if let Some(v) = current {
-            self.cursor += 1;
-            Some(v)
-        } else {
-            return None;
-        }
-    }
-
-    pub fn line(&self) -> usize {
-        // this should only be used under error conditions so it doesn't need to be fast
-        let mut i = 0;
-        for c in self.characters.iter() {
-            if *c == '\n' {
-                i += 1;
-            }
-        }
-
-        return i;
-    }
-}
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 pub struct Parser {
-    scanner: Scanner,
+    source: String,
+    tokens: Vec<Token>,
+    position: usize,
+    path: PathBuf,
+    // spans of the keyword and operands of the command being parsed
+    command_spans: Vec<Range<usize>>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -76,7 +40,7 @@ pub enum ArithmeticType {
     Not,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum CommandDetails {
     Arithmetic(ArithmeticType),
     Push(Segment, i16),
@@ -87,247 +51,270 @@ pub enum CommandDetails {
     Return,
     Call { n_args: i16, symbol: String },
     Goto(String),
+    /// Add a constant to the top of the stack, as `push constant n; add` does.
+    /// Only produced by the optimizer
+    AddConst(i16),
+    /// Pop a value and jump unless it is true (-1), as `not; if-goto` does.
+    /// Only produced by the optimizer
+    IfNotGoto(String),
 }
 
 impl Parser {
-    // constructor
-    pub fn new<R: Read>(mut input_stream: R) -> Parser {
-        let mut reader = String::new();
-        input_stream.read_to_string(&mut reader).unwrap();
+    // constructor. the path is only used to report errors
+    pub fn new<R: Read>(mut input_stream: R, path: &Path) -> TransformResult<Parser> {
+        let mut bytes = Vec::new();
+        input_stream.read_to_end(&mut bytes)
+            .map_err(|e| TransformError::IoError(format!("failed to read '{}': {}", path.display(), e)))?;
+
+        let source = crate::sources::decode(bytes, path)?;
+
+        let tokens = lexer::tokenize(&source);
+
+        Ok(Parser {
+            source,
+            tokens,
+            position: 0,
+            path: path.to_path_buf(),
+            command_spans: Vec::new(),
+        })
+    }
 
-        let parser = Parser {
-            scanner: Scanner::new(reader.chars().collect()),
-        };
+    /// A diagnostic covering the given bytes of the input
+    pub fn diagnostic(&self, severity: Severity, message: String, span: Range<usize>) -> Diagnostic {
+        Diagnostic::new(severity, message, self.path.clone(), &self.source, span)
+    }
 
-        parser
+    /// The path of the file being parsed
+    pub fn path(&self) -> &Path {
+        &self.path
     }
 
-    fn peek_line(&self) -> String {
-        let mut str = String::new();
-        for i in 0.. {
-            let c = self.scanner.peek_n(i);
-            match c {
-                Some(v) => {
-                    if v == &'\n' || v == &'\r' {
-                        break;
-                    } else {
-                        str.push(*v);
-                    }
-                }
-                None => {
-                    break;
-                }
+    /// Whether any line of the file declares a function
+    pub fn declares_function(&self) -> bool {
+        let mut line_start = true;
+        for token in self.tokens.iter() {
+            if line_start && token.kind == TokenKind::Keyword(Keyword::Function) {
+                return true;
             }
+            line_start = token.kind == TokenKind::Newline;
         }
 
-        return str;
+        false
     }
 
-    fn consume_line(&mut self) -> String {
-        let line = self.peek_line();
-        loop {
-            let c = self.scanner.pop();
-            match c {
-                Some('\n') => {
-                    break;
-                }
-                None => {
-                    break;
-                }
-                _ => {}
+    /// The span of the keyword (index 0) or an operand of the last command returned
+    pub fn command_span(&self, index: usize) -> Range<usize> {
+        self.command_spans.get(index)
+            .or(self.command_spans.last())
+            .cloned()
+            .unwrap_or(0..0)
+    }
+
+    // a syntax error covering the given bytes of the input
+    fn syntax_error(&self, message: String, span: Range<usize>) -> TransformError {
+        TransformError::SyntaxError(self.diagnostic(Severity::Error, message, span))
+    }
+
+    // the next token on this line, skipping comments. None at the end of the line
+    fn peek(&self) -> Option<&Token> {
+        self.tokens[self.position..]
+            .iter()
+            .find(|t| t.kind != TokenKind::Comment)
+            .filter(|t| t.kind != TokenKind::Newline)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        if let Some(token) = &token {
+            while self.tokens[self.position] != *token {
+                self.position += 1;
             }
+            self.position += 1;
+            self.command_spans.push(token.span.clone());
         }
 
-        line
+        token
     }
 
-    fn consume_non_whitespace(&mut self) {
-        while let Some(v) = self.scanner.peek() {
-            if v.is_whitespace() {
+    // skip the rest of the current line, including its newline
+    fn skip_line(&mut self) {
+        while let Some(token) = self.tokens.get(self.position) {
+            self.position += 1;
+            if token.kind == TokenKind::Newline {
                 break;
             }
-            self.scanner.pop();
         }
     }
 
-    fn consume_whitespace(&mut self) {
-        loop {
-            match self.scanner.peek() {
-                Some(v) => {
-                    if !v.is_whitespace() {
-                        break;
-                    } else {
-                        let _ = self.scanner.pop();
-                    }
-                }
-                None => {
-                    break;
-                }
-            }
-        }
+    // where the next missing token would have been
+    fn end_of_line_span(&self) -> Range<usize> {
+        let end = self.tokens[self.position..]
+            .iter()
+            .find(|t| t.kind == TokenKind::Newline || t.kind == TokenKind::Comment)
+            .map(|t| t.span.start)
+            .unwrap_or(self.source.len());
+
+        end..end
     }
 
-    fn parse_integer(&mut self) -> i16 {
-        self.consume_whitespace();
-        let rest = self.peek_line();
+    fn parse_integer(&mut self) -> TransformResult<i16> {
+        let token = match self.advance() {
+            Some(token) => token,
+            None => return Err(self.syntax_error("expected an integer".to_string(), self.end_of_line_span())),
+        };
+        let text = token.text(&self.source);
 
-        let mut s = String::new();
-        for c in rest.chars() {
-            if c.is_whitespace() {
-                break;
-            } else {
-                s.push(c);
+        if token.kind != TokenKind::Integer {
+            let err = format!("expected an integer, found '{}'", text);
+            return Err(self.syntax_error(err, token.span));
+        }
+
+        match text.parse() {
+            Ok(v) => Ok(v),
+            Err(_) => {
+                let err = format!("integer '{}' does not fit in 16 bits", text);
+                Err(self.syntax_error(err, token.span))
             }
         }
+    }
 
-        // println!("s='{}'", s);
-        let v = str::parse(s.as_str()).unwrap();
+    fn parse_segment(&mut self) -> TransformResult<Segment> {
+        let token = match self.advance() {
+            Some(token) => token,
+            None => return Err(self.syntax_error("expected a segment".to_string(), self.end_of_line_span())),
+        };
 
-        v
+        let segment = match token.text(&self.source) {
+            "constant" => Segment::Constant,
+            "local" => Segment::Local,
+            "argument" => Segment::Argument,
+            "this" => Segment::This,
+            "that" => Segment::That,
+            "temp" => Segment::Temp,
+            "static" => Segment::Static,
+            "pointer" => Segment::Pointer,
+            word => {
+                let err = format!("unknown segment '{}'", word);
+                return Err(self.syntax_error(err, token.span));
+            }
+        };
+
+        Ok(segment)
     }
 
-    fn parse_segment(&mut self, source_line: &str) -> Segment {
-        self.consume_non_whitespace();
-        self.consume_whitespace();
-        let rest = self.peek_line();
-        let segment;
-        if rest.starts_with("constant") {
-            segment = Segment::Constant;
-        } else if rest.starts_with("local") {
-            segment = Segment::Local;
-        } else if rest.starts_with("argument") {
-            segment = Segment::Argument;
-        } else if rest.starts_with("this") {
-            segment = Segment::This;
-        } else if rest.starts_with("that") {
-            segment = Segment::That;
-        } else if rest.starts_with("temp") {
-            segment = Segment::Temp;
-        } else if rest.starts_with("static") {
-            segment = Segment::Static;
-        } else if rest.starts_with("pointer") {
-            segment = Segment::Pointer;
-        } else {
-            println!("rest='{}'. Line='{}'", rest, source_line);
-            unimplemented!();
+    // function and label names. keywords are not reserved, so `label add` is allowed
+    fn parse_label_symbol(&mut self) -> TransformResult<String> {
+        let token = match self.advance() {
+            Some(token) => token,
+            None => return Err(self.syntax_error("expected a symbol".to_string(), self.end_of_line_span())),
+        };
+
+        let text = token.text(&self.source);
+        if token.kind == TokenKind::Integer {
+            let err = format!("expected a symbol, found '{}'", text);
+            return Err(self.syntax_error(err, token.span));
+        }
+
+        // letters, digits, `_`, `.`, `:` and `$`, not starting with a digit
+        let valid_char = |c: char| c.is_ascii_alphanumeric() || "_.:$".contains(c);
+        if let Some((offset, c)) = text.char_indices().find(|&(_, c)| !valid_char(c)) {
+            let err = format!("illegal character '{}' in symbol '{}'", c, text);
+            let start = token.span.start + offset;
+            return Err(self.syntax_error(err, start..start + c.len_utf8()));
+        }
+        if text.starts_with(|c: char| c.is_ascii_digit()) {
+            let err = format!("symbol '{}' must not start with a digit", text);
+            return Err(self.syntax_error(err, token.span));
         }
 
-        self.consume_non_whitespace();
+        Ok(text.to_string())
+    }
+
+    // the text of the line a command starts on, from the command to the end of the line
+    fn source_line(&self, start: usize) -> String {
+        let end = self.source[start..]
+            .find('\n')
+            .map(|n| start + n)
+            .unwrap_or(self.source.len());
 
-        return segment;
+        self.source[start..end].trim_end().to_string()
     }
 
-    fn parse_label_symbol(&mut self) -> String {
-        self.consume_non_whitespace();
-        self.consume_whitespace();
-        let mut string = String::new();
+    // reuturns none if end of parsing.
+    // after an error, parsing resumes on the next line so every error in a file can be reported
+    pub fn next_command(&mut self) -> Option<TransformResult<(CommandDetails, String)>> {
         loop {
-            if let Some(v) = self.scanner.peek() {
-                // check for a non-alphanumeric character
-                if !(v.is_alphabetic() || *v == '.') {
-                    break;
+            let start = match self.peek() {
+                Some(token) => token.span.start,
+                None if self.position >= self.tokens.len() => return None,
+                None => {
+                    // blank or comment only line
+                    self.skip_line();
+                    continue;
                 }
-                string.push(*self.scanner.pop().unwrap());
-            } else {
-                break;
-            }
-        }
+            };
 
-        self.consume_non_whitespace();
+            self.command_spans.clear();
+            let result = self.parse_command()
+                .map(|command| (command, self.source_line(start)));
+            self.skip_line();
 
-        string
+            return Some(result);
+        }
     }
 
-    // reuturns none if end of parsing
-    pub fn next_command(&mut self) -> Option<TransformResult<(CommandDetails, String)>> {
-        self.consume_line();
-        self.consume_whitespace();
+    fn parse_command(&mut self) -> TransformResult<CommandDetails> {
+        let token = self.advance().expect("a command starts with a token");
+        let keyword = match token.kind {
+            TokenKind::Keyword(keyword) => keyword,
+            _ => {
+                let err = format!("unknown command '{}'", token.text(&self.source));
+                return Err(self.syntax_error(err, token.span));
+            }
+        };
 
-        let rest = self.peek_line();
-        if rest.len() == 0 {
-            return None;
-        }
+        let command = match keyword {
+            Keyword::Pop => {
+                let segment = self.parse_segment()?;
+                let value = self.parse_integer()?;
+                CommandDetails::Pop(segment, value)
+            }
+            Keyword::Push => {
+                let segment = self.parse_segment()?;
+                let value = self.parse_integer()?;
+                CommandDetails::Push(segment, value)
+            }
+            Keyword::Add => CommandDetails::Arithmetic(ArithmeticType::Add),
+            Keyword::Sub => CommandDetails::Arithmetic(ArithmeticType::Sub),
+            Keyword::Eq => CommandDetails::Arithmetic(ArithmeticType::Eq),
+            Keyword::Lt => CommandDetails::Arithmetic(ArithmeticType::Lt),
+            Keyword::Gt => CommandDetails::Arithmetic(ArithmeticType::Gt),
+            Keyword::Neg => CommandDetails::Arithmetic(ArithmeticType::Neg),
+            Keyword::And => CommandDetails::Arithmetic(ArithmeticType::And),
+            Keyword::Or => CommandDetails::Arithmetic(ArithmeticType::Or),
+            Keyword::Not => CommandDetails::Arithmetic(ArithmeticType::Not),
+            Keyword::Label => CommandDetails::Label(self.parse_label_symbol()?),
+            Keyword::IfGoto => CommandDetails::IfGoto(self.parse_label_symbol()?),
+            Keyword::Goto => CommandDetails::Goto(self.parse_label_symbol()?),
+            Keyword::Function => {
+                let symbol = self.parse_label_symbol()?;
+                let n_vars = self.parse_integer()?;
+                CommandDetails::Function { symbol, n_vars }
+            }
+            Keyword::Call => {
+                let symbol = self.parse_label_symbol()?;
+                let n_args = self.parse_integer()?;
+                CommandDetails::Call { symbol, n_args }
+            }
+            Keyword::Return => CommandDetails::Return,
+        };
 
-        if rest.starts_with("pop") {
-            let segment = self.parse_segment(&rest);
-            let value = self.parse_integer();
-            return Some(Ok((CommandDetails::Pop(segment, value), rest.clone())));
-        } else if rest.starts_with("push") {
-            let segment = self.parse_segment(&rest);
-            let value = self.parse_integer();
-            return Some(Ok((CommandDetails::Push(segment, value), rest.clone())));
-        } else if rest.starts_with("//") {
-            return self.next_command();
-        } else if rest.starts_with("add") {
-            return Some(Ok((
-                CommandDetails::Arithmetic(ArithmeticType::Add),
-                rest.clone(),
-            )));
-        } else if rest.starts_with("sub") {
-            return Some(Ok((
-                CommandDetails::Arithmetic(ArithmeticType::Sub),
-                rest.clone(),
-            )));
-        } else if rest.starts_with("eq") {
-            return Some(Ok((
-                CommandDetails::Arithmetic(ArithmeticType::Eq),
-                rest.clone(),
-            )));
-        } else if rest.starts_with("lt") {
-            return Some(Ok((
-                CommandDetails::Arithmetic(ArithmeticType::Lt),
-                rest.clone(),
-            )));
-        } else if rest.starts_with("gt") {
-            return Some(Ok((
-                CommandDetails::Arithmetic(ArithmeticType::Gt),
-                rest.clone(),
-            )));
-        } else if rest.starts_with("neg") {
-            return Some(Ok((
-                CommandDetails::Arithmetic(ArithmeticType::Neg),
-                rest.clone(),
-            )));
-        } else if rest.starts_with("and") {
-            return Some(Ok((
-                CommandDetails::Arithmetic(ArithmeticType::And),
-                rest.clone(),
-            )));
-        } else if rest.starts_with("or") {
-            return Some(Ok((
-                CommandDetails::Arithmetic(ArithmeticType::Or),
-                rest.clone(),
-            )));
-        } else if rest.starts_with("not") {
-            return Some(Ok((
-                CommandDetails::Arithmetic(ArithmeticType::Not),
-                rest.clone(),
-            )));
-        } else if rest.starts_with("label") {
-            let symbol = self.parse_label_symbol();
-            return Some(Ok((CommandDetails::Label(symbol), rest.clone())));
-        } else if rest.starts_with("if-goto") {
-            let symbol = self.parse_label_symbol();
-            return Some(Ok((CommandDetails::IfGoto(symbol), rest.clone())));
-        } else if rest.starts_with("goto") {
-            let symbol = self.parse_label_symbol();
-            return Some(Ok((CommandDetails::Goto(symbol), rest.clone())));
-        } else if rest.starts_with("function") {
-            let symbol = self.parse_label_symbol();
-            let n_vars = self.parse_integer();
-            return Some(Ok((
-                CommandDetails::Function { symbol, n_vars },
-                rest.clone(),
-            )));
-        } else if rest.starts_with("call") {
-            let symbol = self.parse_label_symbol();
-            let n_args = self.parse_integer();
-            return Some(Ok((CommandDetails::Call { symbol, n_args }, rest.clone())));
-        } else if rest.starts_with("return") {
-            return Some(Ok((CommandDetails::Return, rest.clone())));
-        } else {
-            let err = format!("Unimplemented command.'{}'", rest);
-            return Some(Err(TransformError::SyntaxError(err, self.scanner.line())));
+        // nothing but a comment may follow a command
+        if let Some(extra) = self.peek() {
+            let err = format!("unexpected '{}' after command", extra.text(&self.source));
+            return Err(self.syntax_error(err, extra.span.clone()));
         }
+
+        Ok(command)
     }
 }
@@ -1,14 +1,15 @@
 //! A hack assembly emitter that prioritizes ease of implementation
 
-use std::fs::File;
 use std::io::BufWriter;
 
 use std::fmt::{Arguments, Write as FmtWrite};
 use std::io::Write as IoWrite;
-use std::sync::Arc;
 use crate::transformer::Segment;
-use hack_macro::{emit_fmt_hack, emit_hack, fmt_hack, hack_str};
-use crate::transformer::emit::{EContext, EmitAsm};
+use hack_macro::{emit_fmt_hack, emit_hack};
+use crate::transformer::emit::{EContext, EmitAsm, EmitOptions, Lowering, SharedCode, EQ_PROC, GT_PROC, LT_PROC};
+use crate::transformer::emit::{LOGIC_FALSE, LOGIC_TRUE};
+use crate::hack::buffer::InstructionBuffer;
+use crate::hack::instruction::Instruction;
 
 struct SymbolGenerator {
     next_id: usize,
@@ -21,7 +22,7 @@ impl SymbolGenerator {
 
     fn next_commented(&mut self, label_start: &str) -> String {
         assert!(
-            label_start.split_whitespace().skip(1).next().is_none(),
+            label_start.split_whitespace().nth(1).is_none(),
             "no whitespace allowed in labels"
         );
         let mut out = String::new();
@@ -30,19 +31,18 @@ impl SymbolGenerator {
 
         self.next_id += 1;
 
-        return out;
+        out
     }
 }
 
 #[derive(Clone)]
 struct FuncEmitter {
-    returns: usize,
     calls: usize,
 }
 
 impl FuncEmitter {
     fn new() -> FuncEmitter {
-        FuncEmitter { returns: 0, calls: 0 }
+        FuncEmitter { calls: 0 }
     }
 
     // create a new unique id for a call label
@@ -50,43 +50,55 @@ impl FuncEmitter {
         let ret = self.calls;
         self.calls+=1;
 
-        return ret;
-    }
-
-    fn ret(&mut self) -> usize {
-        let ret = self.returns;
-        self.returns+=1;
-
-        return ret;
+        ret
     }
 }
 
-pub struct SimpleEmitter {
-    writer: BufWriter<Arc<File>>,
+pub struct SimpleEmitter<W: IoWrite> {
+    writer: BufWriter<W>,
     symbol_generator: SymbolGenerator,
     emitted_instructions_count: usize,
-    func_emitter: FuncEmitter
+    func_emitter: FuncEmitter,
+    file_name: String,
+    lowering: Lowering,
+    peephole: bool,
+    /// Emitted code that has not been written yet
+    buffer: InstructionBuffer,
 }
 
 
-impl EmitAsm<SContext> for SimpleEmitter {
-    fn with_context(context: SContext, stream: Arc<File>) -> Self {
+impl<W: IoWrite> EmitAsm<SContext, W> for SimpleEmitter<W> {
+    fn with_context(context: SContext, stream: W) -> Self {
         Self::with_context(context, stream)
     }
 
-    fn new(stream: Arc<File>) -> Self {
+    fn new(stream: W) -> Self {
         Self::new(stream)
     }
 
+    fn instruction_count(&self) -> usize {
+        self.emitted_instructions_count + self.buffer.instruction_count()
+    }
+
+    fn flush(&mut self, addresses: &mut [usize]) {
+        self.flush(addresses)
+    }
+
     fn close(self) -> SContext {
         self.close()
     }
 
+    fn begin_file(&mut self, file_name: &str) {
+        self.begin_file(file_name);
+    }
+
     fn emit_init(&mut self) {
         self.emit_init();
     }
 
-    fn prelude(&mut self) {}
+    fn prelude(&mut self) {
+        self.prelude();
+    }
 
     fn comment(&mut self, args: Arguments) -> std::io::Result<()> {
         self.comment(args)
@@ -141,7 +153,7 @@ impl EmitAsm<SContext> for SimpleEmitter {
     }
 
     fn call(&mut self, n_args: i16, symbol: &str) {
-        self.call(n_args, symbol, false)
+        self.call(n_args, symbol)
     }
 
     fn _return(&mut self) {
@@ -211,59 +223,129 @@ impl EmitAsm<SContext> for SimpleEmitter {
     fn not(&mut self) {
         self.not()
     }
+
+    fn add_const(&mut self, val: i16) {
+        SharedCode::add_const(self, val)
+    }
+
+    fn if_not_goto(&mut self, symbol: &str) {
+        SharedCode::if_not_goto(self, symbol)
+    }
+}
+
+impl<W: IoWrite> SharedCode for SimpleEmitter<W> {
+    fn emit(&mut self, instruction: Instruction, note: Option<String>) {
+        self.buffer.push(instruction, note);
+    }
+
+    fn next_label(&mut self, label_start: &str) -> String {
+        self.symbol_generator.next_commented(label_start)
+    }
 }
 
 #[derive(Clone)]
 pub struct SContext {
     emitted_instructions_count: usize,
-    func_emitter: FuncEmitter
+    next_symbol_id: usize,
+    func_emitter: FuncEmitter,
+    lowering: Lowering,
+    peephole: bool,
 }
 
 impl Default for SContext {
     fn default() -> Self {
         Self {
             emitted_instructions_count: 0,
-            func_emitter: FuncEmitter::new()
+            next_symbol_id: 0,
+            func_emitter: FuncEmitter::new(),
+            lowering: Lowering::default(),
+            peephole: false,
         }
     }
 }
 
-impl EContext for SContext {}
+impl EContext for SContext {
+    fn with_options(options: EmitOptions) -> Self {
+        Self {
+            lowering: options.lowering,
+            peephole: options.peephole,
+            ..Self::default()
+        }
+    }
 
-const LOGIC_TRUE: i16 = -1;
-const LOGIC_FALSE: i16 = 0;
+    fn instruction_count(&self) -> usize {
+        self.emitted_instructions_count
+    }
+}
 
-impl SimpleEmitter {
-    pub fn close(self) -> SContext {
-        return SContext {
+impl<W: IoWrite> SimpleEmitter<W> {
+    pub fn close(mut self) -> SContext {
+        self.flush(&mut []);
+        SContext {
             emitted_instructions_count: self.emitted_instructions_count,
+            next_symbol_id: self.symbol_generator.next_id,
             func_emitter: self.func_emitter,
-        };
+            lowering: self.lowering,
+            peephole: self.peephole,
+        }
     }
 
-    pub fn new(stream: Arc<File>) -> SimpleEmitter {
+    pub fn new(stream: W) -> Self {
         SimpleEmitter {
             writer: BufWriter::new(stream),
             symbol_generator: SymbolGenerator::new(),
             emitted_instructions_count: 0,
-            func_emitter: FuncEmitter::new()
+            func_emitter: FuncEmitter::new(),
+            file_name: String::new(),
+            lowering: Lowering::default(),
+            peephole: false,
+            buffer: InstructionBuffer::new(),
         }
     }
 
-    pub fn with_context(emitter_context: SContext, stream: Arc<File>) -> Self {
+    pub fn with_context(emitter_context: SContext, stream: W) -> Self {
         SimpleEmitter {
             writer: BufWriter::new(stream),
-            symbol_generator: SymbolGenerator::new(),
+            symbol_generator: SymbolGenerator { next_id: emitter_context.next_symbol_id },
             emitted_instructions_count: emitter_context.emitted_instructions_count,
-            func_emitter: FuncEmitter::new()
+            func_emitter: emitter_context.func_emitter,
+            file_name: String::new(),
+            lowering: emitter_context.lowering,
+            peephole: emitter_context.peephole,
+            buffer: InstructionBuffer::new(),
         }
     }
 
+    pub fn begin_file(&mut self, file_name: &str) {
+        self.file_name = file_name.to_string();
+    }
+
     pub fn comment(&mut self, args: std::fmt::Arguments) -> std::io::Result<()> {
-        self.write_fmt(args)
+        self.emit(Instruction::Comment(args.to_string()), None);
+        Ok(())
     }
-    fn write_fmt(&mut self, args: std::fmt::Arguments) -> std::io::Result<()> {
-        self.writer.write_fmt(args)
+
+    // write out the buffered code, see `EmitAsm::flush`
+    pub fn flush(&mut self, addresses: &mut [usize]) {
+        let written = self.buffer
+            .flush(&mut self.writer, self.emitted_instructions_count, self.peephole, addresses)
+            .expect("Io error");
+        self.emitted_instructions_count += written;
+    }
+
+    pub fn prelude(&mut self) {
+        if self.lowering != Lowering::Shared {
+            return;
+        }
+
+        let end = self.symbol_generator.next_commented("end_prelude");
+        // skip the shared subroutines when execution falls into them
+        emit_fmt_hack!(r"
+            @{end}
+            0;JMP
+        ");
+        self.comparison_procs();
+        self.emit_label_start(end.as_str());
     }
 
     pub fn emit_init(&mut self) {
@@ -294,65 +376,8 @@ impl SimpleEmitter {
             M=D        // initialize segment pointers to a known value
         "};
 
-        self.call(0,"Sys.init", true);
-        self.emitln("");
-
-    }
+        self.call(0,"Sys.init");
 
-    fn emitln(&mut self, str: &str) {
-        let lines = str.split('\n');
-        for line in lines {
-            let line = line.trim();
-
-            // if line is instruction, display the instruction number for debugging convenience
-            if !line.starts_with("//") && !line.starts_with("(") && !line.is_empty() {
-                let no = self.emitted_instructions_count;
-                self.write_fmt(format_args!("{:90}//{:3}\n", line, no))
-                    .unwrap();
-                self.emitted_instructions_count += 1;
-            } else {
-                self.write_fmt(format_args!("{:90}\n", line)).unwrap();
-            }
-        }
-    }
-
-    fn emit_label_start(&mut self, symbol: &str) {
-        emit_fmt_hack!("({})", symbol);
-    }
-
-
-    // puts the last item into register A
-    // clobbers A
-    // tested
-    fn stack_to_a(&mut self) {
-        emit_hack! {r"
-            @SP
-            M=M-1       // Decrement stack pointer
-            A=M         // A = Stack pointer
-            A=M         // A = old top of stack
-        "};
-    }
-
-    // tested
-    // clobbers, A, D
-    fn stack_to_d(&mut self) {
-        emit_hack! {r"
-            @SP
-            M=M-1       // Decrement stack pointer
-            A=M         // A = Stack pointer
-            D=M         // D = old top of stack
-        "};
-    }
-
-    // push the item in register A onto the stack
-    fn a_to_stack(&mut self) {
-        emit_hack! {r"
-            D=A
-            @SP
-            M=M+1       // increase stack pointer
-            A=M-1       // get top of stack
-            M=D         // top of stack = D
-        "};
     }
 
     fn d_to_stack(&mut self) {
@@ -364,30 +389,6 @@ impl SimpleEmitter {
         "};
     }
 
-    fn emit_push_local_a(&mut self) {
-        emit_hack! {r"
-            // emit_push_a
-            D=A
-            @LCL
-            A=M     // A=*LCL
-            M=D     // **LCL = D
-            @SP
-            M=M+1   //*LCL = *LCL+1
-        "};
-    }
-
-    fn assign_a(&mut self, value: i16) {
-        emit_fmt_hack!(r"
-            @{0} // A = {0}
-        ", value);
-    }
-
-    fn assign_a_str(&mut self, value: &str) {
-        emit_fmt_hack!(r"
-            @{0}    // A = {0}
-        ", value);
-    }
-
     // tested
     pub fn push_const(&mut self, val: i16) {
         self.const_to_stack(val);
@@ -403,6 +404,11 @@ impl SimpleEmitter {
 
 
     pub fn eq(&mut self) {
+        if self.lowering == Lowering::Shared {
+            self.call_comparison(EQ_PROC);
+            return;
+        }
+
         self.stack_to_temp(TempRegister::T0);
         self.stack_to_temp(TempRegister::T1);
         self.sub_temp(TempRegister::T0, TempRegister::T0, TempRegister::T1);
@@ -417,11 +423,15 @@ impl SimpleEmitter {
         self.sub_temp(TempRegister::T0, TempRegister::T1, TempRegister::T0);
         self.temp_to_stack(TempRegister::T0);
 
-        self.emitln("");
     }
 
     // tested!
     pub fn lt(&mut self) {
+        if self.lowering == Lowering::Shared {
+            self.call_comparison(LT_PROC);
+            return;
+        }
+
         let is_lt = self.symbol_generator.next_commented("is_lt");
         let is_not_lt = self.symbol_generator.next_commented("is_not_lt");
         let end = self.symbol_generator.next_commented("end");
@@ -447,11 +457,15 @@ impl SimpleEmitter {
             M=D      // write value to stack
         ");
 
-        self.emitln("");
     }
 
     // tested
     pub fn gt(&mut self) {
+        if self.lowering == Lowering::Shared {
+            self.call_comparison(GT_PROC);
+            return;
+        }
+
         let is_gt = self.symbol_generator.next_commented("is_gt");
         let is_not_gt = self.symbol_generator.next_commented("is_not_gt");
         let end = self.symbol_generator.next_commented("end");
@@ -475,7 +489,6 @@ impl SimpleEmitter {
             A=M-1   // address of top item in stack
             M=D     // write result to top of stack
         ");
-        self.emitln("");
     }
 
     // tested
@@ -487,7 +500,6 @@ impl SimpleEmitter {
             D=-D            // negate value
             M=D             // write result on stack
         "};
-        self.emitln("");
     }
 
     // tested
@@ -499,7 +511,6 @@ impl SimpleEmitter {
             A=M-1       // pointer to last item on stack
             M=M|D       // write result to stack
         "};
-        self.emitln("");
     }
 
     // tested
@@ -512,7 +523,6 @@ impl SimpleEmitter {
             D=!D        // calculate
             M=D         // write result to stack
         "};
-        self.emitln("");
     }
 
     pub fn and(&mut self) {
@@ -523,22 +533,23 @@ impl SimpleEmitter {
             A=M-1       // A = 2nd item from stack
             M=M&D       // write result to stack, overwriting 2nd item
         "};
-        self.emitln("");
     }
 
     fn segment_symbol_str(&self, segment: Segment, _offset: i16) -> &str {
-        return match segment {
+        match segment {
             Segment::Local => "LCL",
             Segment::Constant => {
                 unreachable!("Constant is not a real segment")
             }
             Segment::Argument => "ARG",
             Segment::Temp => "TMP",
-            Segment::Static => "STATIC",
+            Segment::Static => {
+                unreachable!("Static is addressed through per-file symbols")
+            }
             Segment::That => "THAT",
             Segment::This => "THIS",
             Segment::Pointer => "THIS",
-        };
+        }
     }
 
     // move the value at offset n from the segment onto the stack
@@ -594,7 +605,6 @@ impl SimpleEmitter {
             M=M-1       // Decrease stack pointer
         "};
 
-        self.emitln("");
     }
 
     // move the value from the stack to the segment at offset n
@@ -633,7 +643,6 @@ impl SimpleEmitter {
             A=M-1   // A = value at top of stack
             M=D     // write value to stack
         "};
-        self.emitln("");
     }
 
     // take the argument at offset n and place it on the stack
@@ -671,8 +680,21 @@ impl SimpleEmitter {
         self.push_non_stack_segment(Segment::Temp, n);
     }
 
+    // statics are mangled per file as `FileName.n` so that each file gets its own variables
+    fn static_symbol(&self, n: i16) -> String {
+        format!("{}.{}", self.file_name, n)
+    }
+
     pub fn push_static_n(&mut self, n: i16) {
-        self.push_non_stack_segment(Segment::Static, n);
+        let symbol = self.static_symbol(n);
+        emit_fmt_hack!(r"
+            @{symbol}
+            D=M     // D = value of static
+            @SP
+            M=M+1   // increase stack pointer
+            A=M-1   // A = top of stack
+            M=D     // write value to stack
+        ");
     }
 
     pub fn push_this_n(&mut self, n: i16) {
@@ -684,7 +706,12 @@ impl SimpleEmitter {
     }
 
     pub fn pop_static_n(&mut self, n: i16) {
-        self.pop_non_stack_segment(Segment::Static, n);
+        let symbol = self.static_symbol(n);
+        self.stack_to_d();
+        emit_fmt_hack!(r"
+            @{symbol}
+            M=D     // write value to static
+        ");
     }
 
     pub fn pop_local_n(&mut self, n: i16) {
@@ -695,28 +722,25 @@ impl SimpleEmitter {
         self.pop_non_stack_segment(Segment::Pointer, n);
     }
 
-    const USER_LABEL_PREFIX: &'static str = "user_";
-    // declare the start of a label. mangles the label
+    // declare the start of a label. the symbol is already mangled by the writer
     pub fn label(&mut self, symbol: &str) {
-        emit_fmt_hack!{r"({}{})", Self::USER_LABEL_PREFIX, symbol};
+        self.emit_label_start(symbol);
     }
 
     // jump to the symbol if stack top > 0
     pub fn ifgoto(&mut self, symbol: &str) {
         self.stack_to_d();
-        emit_fmt_hack! {r"@{}{}", Self::USER_LABEL_PREFIX, symbol};
+        emit_fmt_hack! {r"@{}", symbol};
         emit_hack! {r"
             D;JNE
         "};
-        self.emitln("");
     }
 
     pub fn goto(&mut self, symbol: &str) {
-        emit_fmt_hack! {r"@{}{}", Self::USER_LABEL_PREFIX, symbol};
+        emit_fmt_hack! {r"@{}", symbol};
         emit_hack! {r"
             0;JMP
         "};
-        self.emitln("");
     }
 
 
@@ -859,37 +883,6 @@ impl SimpleEmitter {
     }
 
 
-    fn segment_pointer_to_stack(&mut self, segment: Segment) {
-        // let not_temp_segment;
-        let segment_symbol = self.segment_symbol_str(segment, -1);
-
-        // D = address of segment start
-        match segment {
-            Segment::Pointer => {
-                emit_fmt_hack!("@{}", segment_symbol);
-                emit_hack! {r"
-                    D=A         // D = segment start
-                "};
-            }
-            Segment::Temp => {panic!("temp has no segment pointer"); }
-            _ => {
-                emit_fmt_hack!("@{}", segment_symbol);
-                emit_hack! {r"
-                    D=M         // D = segment start
-                "};
-            }
-        }
-
-        // A = segment offset
-        emit_hack! {r"
-            @SP
-            A=M         // A = stack pointer
-            M=D         // write segment pointer to stack
-            @SP
-            M=M+1       // increase stack pointer
-        "};
-    }
-
     // clobbers A
     fn sp_at_offset(&mut self, mut offset: i16) {
         if offset > 0 {
@@ -910,10 +903,10 @@ impl SimpleEmitter {
 
     }
 
-    pub fn call(&mut self, mut n_args: i16, callee_symbol: &str, first_call: bool) {
+    pub fn call(&mut self, n_args: i16, callee_symbol: &str) {
         assert!(n_args >= 0);
 
-        let ret_label = fmt_hack!("{}$ret.{}", callee_symbol, self.func_emitter.call());
+        let ret_label = format!("{}$ret.{}", callee_symbol, self.func_emitter.call());
 
         // save stackframe
             // make room for values
@@ -1013,8 +1006,8 @@ impl SimpleEmitter {
             // set arg pointer to first argument
             // it must point to the first argument, or caller_return_address if none
             self.sp_at_offset(caller_return_address);
-            if (n_args > 0) {
-                for i in 0..n_args {
+            if n_args > 0 {
+                for _ in 0..n_args {
                     emit_fmt_hack!(r"
                     A=A-1
                 ");
@@ -1069,17 +1062,6 @@ impl SimpleEmitter {
         self.d_to_stack();
     }
 
-    fn const_to_temp(&mut self, value: i16, reg: TempRegister) {
-        let register_offset = reg as usize;
-        self.assign_a(value);
-        emit_fmt_hack!(r"
-            D=A
-            @{0}
-            M=D
-        ", register_offset);
-
-    }
-
 
     // if the tmp register is zero, set it to LOGIC_TRUE, else set it to LOGIC_FALSE
     fn not_zero_tmp(&mut self, tmp: TempRegister) {
@@ -1154,5 +1136,4 @@ impl SimpleEmitter {
 enum TempRegister {
     T0 = 13,
     T1 = 14,
-    T2 = 15,
 }
\ No newline at end of file
@@ -1,167 +1,90 @@
-use std::fs::{DirEntry, File};
-use std::io::{Read, Write};
+use std::io::Read;
+use std::path::Path;
 
-use std::path::{Path, PathBuf};
-
-use super::parser::Parser;
-use super::writer::{CodeWriter, WriterContext};
-use super::{parser, transform as transformer, writer};
-use std::sync::Arc;
-use crate::transformer::compact_emitter::{CompactEmitter, CEmitterContext};
-use crate::transformer::emit::{EContext, EmitAsm};
-use crate::transformer::simple_emitter::SimpleEmitter;
+use super::parser::{CommandDetails, Parser};
+use super::validate::Validator;
+use crate::transformer::diagnostic::Diagnostic;
+use crate::sizes::SizeTable;
 
 pub type TransformResult<T> = Result<T, TransformError>;
 
 #[derive(Clone, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum TransformError {
-    SyntaxError(String, usize),
+    SyntaxError(Diagnostic),
+    /// A well formed command that breaks the bounds of the VM spec
+    ValidationError(Diagnostic),
+    SemanticError(String),
     IoError(String),
+    /// The program does not fit in the ROM budget. Carries the size of every function
+    RomBudgetExceeded { budget: usize, sizes: SizeTable },
+    /// Every error found in a file
+    Multiple(Vec<TransformError>),
+}
+
+impl TransformError {
+    // a single error is reported as itself rather than a list of one
+    pub(crate) fn from_errors(mut errors: Vec<TransformError>) -> TransformError {
+        if errors.len() == 1 {
+            errors.pop().unwrap()
+        } else {
+            TransformError::Multiple(errors)
+        }
+    }
 }
 
 impl std::fmt::Display for TransformError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            TransformError::SyntaxError(msg, line) => {
-                write!(f, "Syntax Error: {} on line {}", msg, line)
-            }
+            TransformError::SyntaxError(diagnostic) => write!(f, "{}", diagnostic),
+            TransformError::ValidationError(diagnostic) => write!(f, "{}", diagnostic),
+            TransformError::SemanticError(msg) => write!(f, "Semantic Error: {}", msg),
             TransformError::IoError(msg) => write!(f, "IO Error: {}", msg),
+            TransformError::RomBudgetExceeded { budget, sizes } => write!(
+                f,
+                "error: the program needs {} words of ROM, more than the budget of {}",
+                sizes.total(),
+                budget
+            ),
+            TransformError::Multiple(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "\n\n")?;
+                    }
+                    write!(f, "{}", error)?;
+                }
+                write!(f, "\n\n{} errors", errors.len())
+            }
         }
     }
 }
 
-/// A type the emits hack instructions
-type Emitter = super::simple_emitter::SimpleEmitter;
-
-/// A type that encapsulates emitter state
-type EmitterContext = super::simple_emitter::SContext;
-
-pub fn transform_file(
-    writer_context: WriterContext<EmitterContext>,
-    in_file_path: &Path,
-    out_steam: Arc<File>,
-    errored: &mut bool,
-    emit_init: bool,
-    out_path: PathBuf
-) -> WriterContext<EmitterContext>
-{
-
-    // todo: not correct for files in a folder
-    // let out_path = crate::assume_output_path(&in_file_path);
-
-    let file_in = std::fs::File::open(&in_file_path).expect("Failed to open input file");
-
-    println!(
-        "Transforming file '{:60}'   ==>   '{}'",
-        in_file_path.display(),
-        out_path.display()
-    );
-
-    let result = transform::<_,Emitter>(
-        writer_context.clone(),
-        file_in,
-        out_steam.clone(),
-        emit_init,
-    );
-
-    let new_state = match result {
-        Ok(s) => s,
-        Err(error) => {
-            eprintln!("                  ^----- '{:?}'", error.to_string());
-            *errored = true;
-
-            writer_context
-        }
-    };
+impl std::error::Error for TransformError {}
 
-    return new_state;
-}
-
-pub fn visit_dir_entry(
-    dir: DirEntry,
-    out_stream: Arc<File>,
-    writer_context: WriterContext<EmitterContext>,
-    translate_error: &mut bool,
-    inject_init: bool,
-    out_path: PathBuf
-) -> WriterContext<EmitterContext>
+/// Parse and validate a whole file.
+/// Parsing carries on after an error so that every error in the file is reported.
+pub fn parse_file<R: Read>(
+    in_stream: R,
+    in_path: &Path,
+    warnings: &mut Vec<Diagnostic>,
+) -> TransformResult<Vec<(CommandDetails, String)>>
 {
-    let mut context = writer_context;
+    let mut reader = Validator::new(Parser::new(in_stream, in_path)?);
 
-    // recursively visit all subdirectories
-    if dir.file_type().unwrap().is_dir() {
-        let path = dir.path();
-        for d in path.read_dir().unwrap() {
-            context = visit_dir_entry(
-                d.unwrap(),
-                out_stream.clone(),
-                context,
-                translate_error,
-                inject_init,
-                out_path.clone()
-            );
-        }
-    } else {
-        let path = dir.path();
-        if path.extension() == Some("vm".as_ref()) {
-            context = transformer::transform_file(
-                context,
-                path.as_path(),
-                out_stream,
-                translate_error,
-                inject_init,
-                out_path
-            );
+    let mut commands = Vec::new();
+    let mut errors = Vec::new();
+    while let Some(val) = reader.next_command() {
+        match val {
+            Ok(command) => commands.push(command),
+            Err(error) => errors.push(error),
         }
     }
 
-    context
-}
-
-pub fn transform_directory(
-    path: &Path,
-    translate_error: &mut bool,
-    out_stream: Arc<File>,
-    emit_init: bool,
-    writer_context: WriterContext<EmitterContext>,
-    out_path: PathBuf
-)
-{
-    let mut context = writer_context;
-    for entry in std::fs::read_dir(path).unwrap() {
-        let entry = entry.unwrap();
-
-        let mut out_stream = out_stream.clone();
-        out_stream.flush();
-
-        context = visit_dir_entry(
-            entry,
-            out_stream,
-            context,
-            translate_error,
-            emit_init,
-            out_path.clone()
-        );
-    }
-}
-fn transform<R: Read, E>
-(
-    writer_context: WriterContext<EmitterContext>,
-    in_stream: R,
-    out_stream: Arc<File>,
-    emit_init: bool,
-) -> Result<WriterContext<EmitterContext>, TransformError>
-        where E: EmitAsm<EmitterContext>
-{
-    let mut reader: Parser = parser::Parser::new(in_stream);
-    let mut writer: CodeWriter<EmitterContext, Emitter> =
-        writer::CodeWriter::with_context(writer_context, out_stream, emit_init);
-
-    while let Some(val) = reader.next_command() {
-        let (command, line) = val?;
+    warnings.extend_from_slice(reader.warnings());
 
-        writer.write_command(&command, &line);
+    if !errors.is_empty() {
+        return Err(TransformError::from_errors(errors));
     }
 
-    return Ok(writer.close());
+    Ok(commands)
 }
@@ -0,0 +1,281 @@
+//! Checks parsed commands against the bounds of the VM spec before any code is written for them
+
+use std::collections::{HashSet, VecDeque};
+
+use super::diagnostic::{Diagnostic, Severity};
+use super::emit::RESERVED_SYMBOLS;
+use crate::hack::instruction::is_symbol;
+use super::parser::{CommandDetails, Parser, Segment};
+use super::{TransformError, TransformResult};
+
+/// Number of words in the temp segment (RAM[5..13])
+const TEMP_SIZE: i16 = 8;
+/// Number of words in the pointer segment (THIS and THAT)
+const POINTER_SIZE: i16 = 2;
+/// Number of words the spec gives the static segment (RAM[16..256])
+const STATIC_SIZE: i16 = 240;
+
+/// Wraps a [`Parser`], rejecting commands whose operands are out of range and labels that are
+/// used outside of the function they are declared in.
+///
+/// A file without any function, like the project 8 loop tests, may use labels at the top level.
+/// They are checked like the labels of one function, and only warned about.
+///
+/// Errors are returned in place of the command. Warnings are collected and the command is
+/// passed through unchanged. A jump to a label its function never declares is only known to be
+/// wrong at the end of the function, so that error comes after the function's last command.
+pub struct Validator {
+    parser: Parser,
+    warnings: Vec<Diagnostic>,
+    function: Option<FunctionScope>,
+    /// Labels already reported as used outside of any function, as an error or a warning
+    unscoped: HashSet<String>,
+    /// Errors found at the end of a function, returned before the next command
+    pending: VecDeque<TransformError>,
+    /// Whether a static has been used yet, so a file name that can not name statics is
+    /// reported once
+    statics_used: bool,
+}
+
+/// Labels declared and jumped to inside the function being read
+struct FunctionScope {
+    /// None for the top level of a file without functions
+    name: Option<String>,
+    labels: HashSet<String>,
+    /// The first jump to each label, with the error to report if it is never declared
+    jumps: Vec<(String, Diagnostic)>,
+}
+
+impl Validator {
+    pub fn new(parser: Parser) -> Validator {
+        let function = if parser.declares_function() { None } else { Some(FunctionScope::top_level()) };
+
+        Validator {
+            parser,
+            warnings: Vec::new(),
+            function,
+            unscoped: HashSet::new(),
+            pending: VecDeque::new(),
+            statics_used: false,
+        }
+    }
+
+    /// Warnings found so far
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Same as [`Parser::next_command`], with every command checked
+    pub fn next_command(&mut self) -> Option<TransformResult<(CommandDetails, String)>> {
+        if let Some(error) = self.pending.pop_front() {
+            return Some(Err(error));
+        }
+
+        let (command, line) = match self.parser.next_command() {
+            Some(Ok(val)) => val,
+            Some(Err(error)) => return Some(Err(error)),
+            None => {
+                self.end_function();
+                return self.pending.pop_front().map(Err);
+            }
+        };
+
+        if let Some((severity, message, operand)) = check(&command) {
+            let span = self.parser.command_span(operand);
+            let diagnostic = self.parser.diagnostic(severity, message, span);
+
+            if severity == Severity::Error {
+                return Some(Err(TransformError::ValidationError(diagnostic)));
+            }
+            self.warnings.push(diagnostic);
+        }
+
+        if let Some(message) = self.check_static_prefix(&command) {
+            let diagnostic = self.parser.diagnostic(Severity::Error, message, self.parser.command_span(1));
+            return Some(Err(TransformError::ValidationError(diagnostic)));
+        }
+
+        if let Some(message) = self.check_scope(&command) {
+            let diagnostic = self.parser.diagnostic(Severity::Error, message, self.parser.command_span(1));
+            return Some(Err(TransformError::ValidationError(diagnostic)));
+        }
+
+        Some(Ok((command, line)))
+    }
+
+    // track the function being read and check the labels used in it
+    fn check_scope(&mut self, command: &CommandDetails) -> Option<String> {
+        let (label, declaration) = match command {
+            CommandDetails::Function { symbol, .. } => {
+                self.end_function();
+                self.function = Some(FunctionScope::new(symbol));
+                return None;
+            }
+            CommandDetails::Label(label) => (label, true),
+            CommandDetails::Goto(label) | CommandDetails::IfGoto(label) | CommandDetails::IfNotGoto(label) => {
+                (label, false)
+            }
+            _ => return None,
+        };
+
+        let scope = match self.function.as_mut() {
+            Some(scope) => scope,
+            None if self.unscoped.insert(label.clone()) => {
+                return Some(format!("label '{}' is used outside of any function", label));
+            }
+            None => return None,
+        };
+
+        if scope.name.is_none() && self.unscoped.insert(label.clone()) {
+            let message = format!("label '{}' is used outside of any function, so it is not scoped to one", label);
+            let diagnostic = self.parser.diagnostic(Severity::Warning, message, self.parser.command_span(1));
+            self.warnings.push(diagnostic);
+        }
+
+        if declaration {
+            if is_return_label(label) {
+                return Some(format!(
+                    "label '{}' is reserved for the return addresses of calls, which are named `Function$ret.N`",
+                    label
+                ));
+            }
+            if !scope.labels.insert(label.clone()) {
+                return Some(format!("label '{}' is declared more than once in {}", label, scope.describe()));
+            }
+        } else if !scope.jumps.iter().any(|(jump, _)| jump == label) {
+            let message = format!(
+                "label '{}' is not declared in {}. Jumps may not cross function boundaries",
+                label,
+                scope.describe()
+            );
+            let diagnostic = self.parser.diagnostic(Severity::Error, message, self.parser.command_span(1));
+            scope.jumps.push((label.clone(), diagnostic));
+        }
+
+        None
+    }
+
+    // statics are named `File.i` after the file, so its name has to make a valid Hack symbol
+    fn check_static_prefix(&mut self, command: &CommandDetails) -> Option<String> {
+        match command {
+            CommandDetails::Push(Segment::Static, _) | CommandDetails::Pop(Segment::Static, _) => {}
+            _ => return None,
+        }
+        if std::mem::replace(&mut self.statics_used, true) {
+            return None;
+        }
+
+        let stem = self.parser.path().file_stem().unwrap_or_default().to_string_lossy();
+        if is_symbol(&stem) {
+            return None;
+        }
+
+        Some(format!(
+            "file name '{}' cannot name statics, which are the symbols '{}.i'. A file name may only \
+             contain letters, digits, '_', '.', '$' and ':' and may not start with a digit",
+            stem, stem
+        ))
+    }
+
+    // report the jumps of the function just finished to labels it never declared
+    fn end_function(&mut self) {
+        if let Some(scope) = self.function.take() {
+            for (jump, diagnostic) in scope.jumps {
+                if !scope.labels.contains(&jump) {
+                    self.pending.push_back(TransformError::ValidationError(diagnostic));
+                }
+            }
+        }
+    }
+}
+
+impl FunctionScope {
+    fn new(name: &str) -> FunctionScope {
+        FunctionScope {
+            name: Some(name.to_string()),
+            labels: HashSet::new(),
+            jumps: Vec::new(),
+        }
+    }
+
+    fn top_level() -> FunctionScope {
+        FunctionScope {
+            name: None,
+            labels: HashSet::new(),
+            jumps: Vec::new(),
+        }
+    }
+
+    // where the labels are declared, for messages
+    fn describe(&self) -> String {
+        match &self.name {
+            Some(name) => format!("function '{}'", name),
+            None => "the file".to_string(),
+        }
+    }
+}
+
+// whether `Function$label` could be the return label `Callee$ret.N` of some call, which is
+// when the label is `ret.N` or ends in `$ret.N`
+fn is_return_label(label: &str) -> bool {
+    let last = label.rsplit('$').next().unwrap_or(label);
+    match last.strip_prefix("ret.") {
+        Some(n) => !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+// the problem with a command, if any, and the index of the operand it is about
+fn check(command: &CommandDetails) -> Option<(Severity, String, usize)> {
+    let error = |message: String, operand| Some((Severity::Error, message, operand));
+
+    match command {
+        CommandDetails::Pop(Segment::Constant, _) => {
+            error("cannot pop to the constant segment".to_string(), 1)
+        }
+        CommandDetails::Push(segment, index) | CommandDetails::Pop(segment, index) => {
+            check_index(*segment, *index)
+        }
+        CommandDetails::Function { n_vars, .. } if *n_vars < 0 => {
+            error(format!("function cannot have a negative number of locals ({})", n_vars), 2)
+        }
+        CommandDetails::Call { n_args, .. } if *n_args < 0 => {
+            error(format!("call cannot pass a negative number of arguments ({})", n_args), 2)
+        }
+        CommandDetails::Function { symbol, .. } | CommandDetails::Call { symbol, .. } if RESERVED_SYMBOLS.contains(&symbol.as_str()) => {
+            error(format!("function name '{}' is reserved for the translator's own subroutines", symbol), 1)
+        }
+        _ => None,
+    }
+}
+
+fn check_index(segment: Segment, index: i16) -> Option<(Severity, String, usize)> {
+    let name = format!("{:?}", segment).to_lowercase();
+
+    // integers are parsed as i16, so a constant above 32767 has already been rejected
+    if segment == Segment::Constant && index < 0 {
+        let message = format!("constant {} is out of range 0..32767", index);
+        return Some((Severity::Error, message, 2));
+    }
+    if index < 0 {
+        let message = format!("{} index cannot be negative ({})", name, index);
+        return Some((Severity::Error, message, 2));
+    }
+
+    let message = match segment {
+        Segment::Temp if index >= TEMP_SIZE => {
+            format!("temp index {} is out of range 0..{}", index, TEMP_SIZE - 1)
+        }
+        Segment::Pointer if index >= POINTER_SIZE => {
+            format!("pointer index {} is out of range 0..{}", index, POINTER_SIZE - 1)
+        }
+        Segment::Static if index >= STATIC_SIZE => {
+            // statics are assembler symbols here, so a large index still gets a slot
+            let message = format!("static index {} is beyond the {} words of the static segment", index, STATIC_SIZE);
+            return Some((Severity::Warning, message, 2));
+        }
+        _ => return None,
+    };
+
+    Some((Severity::Error, message, 2))
+}
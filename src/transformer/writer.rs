@@ -1,20 +1,23 @@
-use super::simple_emitter::{SimpleEmitter, SContext};
 use super::parser::{ArithmeticType, Segment};
-use std::fs::File;
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::io::Write;
 
 use super::parser::CommandDetails;
-use super::emit::{EContext, EmitAsm};
+use super::emit::{EContext, EmitAsm, EmitOptions};
+use super::{TransformError, TransformResult};
 
-pub struct CodeWriter<C, E>
+pub struct CodeWriter<C, E, W>
     where C: EContext,
-    E: EmitAsm<C>
+    E: EmitAsm<C, W>,
+    W: Write
 {
     emit: E,
     first_run: bool,
     emit_init: bool,
-    _phantom: PhantomData<C>
+    /// Name of the function being written, which labels are scoped to
+    function: Option<String>,
+    command_addresses: Vec<usize>,
+    _phantom: PhantomData<(C, W)>
 }
 
 #[derive(Clone)]
@@ -23,6 +26,31 @@ pub struct WriterContext<C>
         C: EContext,
 {
     emitter_sate: C,
+    first_run: bool,
+    command_addresses: Vec<usize>,
+}
+
+impl<C> WriterContext<C>
+    where
+        C: EContext,
+{
+    /// The ROM address of the first instruction emitted for every command written so far
+    pub fn command_addresses(&self) -> &[usize] {
+        &self.command_addresses
+    }
+
+    /// Number of instructions written so far
+    pub fn instruction_count(&self) -> usize {
+        self.emitter_sate.instruction_count()
+    }
+
+    /// A fresh context that emits code the given way
+    pub fn with_options(options: EmitOptions) -> Self {
+        Self {
+            emitter_sate: C::with_options(options),
+            ..Self::default()
+        }
+    }
 }
 
 impl<C> Default for WriterContext<C>
@@ -32,49 +60,73 @@ impl<C> Default for WriterContext<C>
     fn default() -> Self {
         Self {
             emitter_sate: C::default(),
+            first_run: true,
+            command_addresses: Vec::new(),
         }
     }
 }
-impl<C, E> CodeWriter<C, E>
+impl<C, E, W> CodeWriter<C, E, W>
     where C: EContext,
-    E: EmitAsm<C>
+    E: EmitAsm<C, W>,
+    W: Write
 {
 
     pub fn with_context(
         writer_context: WriterContext<C>,
-        output_stream: Arc<File>,
+        output_stream: W,
         emit_init: bool,
+        file_name: &str,
     ) -> Self {
-        let writer  = E::with_context(writer_context.emitter_sate, output_stream);
+        let mut writer  = E::with_context(writer_context.emitter_sate, output_stream);
+        writer.begin_file(file_name);
 
         CodeWriter {
             emit: writer,
-            first_run: true,
+            first_run: writer_context.first_run,
             emit_init,
-            _phantom: PhantomData::default()
+            function: None,
+            command_addresses: writer_context.command_addresses,
+            _phantom: PhantomData
         }
     }
 
     // constructor
 
-    pub fn new(output_stream: Arc<File>, emit_init: bool) -> CodeWriter<C, E> {
-        let writer = E::new(output_stream);
+    pub fn new(output_stream: W, emit_init: bool, file_name: &str) -> CodeWriter<C, E, W> {
+        let mut writer = E::new(output_stream);
+        writer.begin_file(file_name);
 
         CodeWriter {
             emit: writer,
             first_run: true,
             emit_init,
-            _phantom: PhantomData::default()
+            function: None,
+            command_addresses: Vec::new(),
+            _phantom: PhantomData
         }
     }
 
-    pub fn close(self) -> WriterContext<C> {
-        WriterContext {
+    pub fn close(mut self) -> TransformResult<WriterContext<C>> {
+        self.emit.flush(&mut self.command_addresses);
+
+        Ok(WriterContext {
             emitter_sate: self.emit.close(),
+            first_run: self.first_run,
+            command_addresses: self.command_addresses,
+        })
+    }
+
+    // labels are scoped to their function as `Function$label`. The Validator has already
+    // rejected jumps across functions, and only lets labels outside of a function through in
+    // files without functions, where they are left as they are
+    fn scoped_label(&self, label: &str) -> String {
+        match self.function.as_deref() {
+            Some(function) => format!("{}${}", function, label),
+            None => label.to_string(),
         }
     }
 
-    pub fn write_command(&mut self, command: &CommandDetails, source: &String) {
+    pub fn write_command(&mut self, command: &CommandDetails, source: &String) -> TransformResult<()> {
         if self.first_run {
 
             if self.emit_init {
@@ -86,13 +138,15 @@ impl<C, E> CodeWriter<C, E>
             self.first_run = false;
         }
 
+        self.command_addresses.push(self.emit.instruction_count());
+
         // if self.first_run && self.emit_init {
         //     self.emit.emit_init();
         //     self.first_run = false;
         // }
 
         self.emit
-            .comment(format_args!("//{}\n", source))
+            .comment(format_args!("{}", source))
             .expect("Io error");
 
         match command {
@@ -107,7 +161,12 @@ impl<C, E> CodeWriter<C, E>
                 Segment::Pointer => self.emit.push_ptr_n(*arg1),
             },
             CommandDetails::Pop(segment, arg1) => match segment {
-                Segment::Constant => unimplemented!("cannot pop a constant"),
+                Segment::Constant => {
+                    return Err(TransformError::SemanticError(format!(
+                        "cannot pop to the constant segment in '{}'",
+                        source.trim()
+                    )));
+                }
                 Segment::Local => self.emit.pop_local_n(*arg1),
                 Segment::Argument => self.emit.pop_argument_n(*arg1),
                 Segment::Temp => self.emit.pop_temp_n(*arg1),
@@ -126,14 +185,31 @@ impl<C, E> CodeWriter<C, E>
             CommandDetails::Arithmetic(ArithmeticType::Or) => self.emit.or(),
             CommandDetails::Arithmetic(ArithmeticType::Not) => self.emit.not(),
 
-            CommandDetails::Label(symbol) => self.emit.label(symbol),
-            CommandDetails::Goto(symbol) => self.emit.goto(symbol.as_str()),
-            CommandDetails::IfGoto(symbol) => self.emit.ifgoto(symbol.as_str()),
+            CommandDetails::Label(symbol) => {
+                let label = self.scoped_label(symbol);
+                self.emit.label(label.as_str())
+            }
+            CommandDetails::Goto(symbol) => {
+                let label = self.scoped_label(symbol);
+                self.emit.goto(label.as_str())
+            }
+            CommandDetails::IfGoto(symbol) => {
+                let label = self.scoped_label(symbol);
+                self.emit.ifgoto(label.as_str())
+            }
+            CommandDetails::IfNotGoto(symbol) => {
+                let label = self.scoped_label(symbol);
+                self.emit.if_not_goto(label.as_str())
+            }
+            CommandDetails::AddConst(value) => self.emit.add_const(*value),
             CommandDetails::Function { n_vars, symbol } => {
+                self.function = Some(symbol.clone());
                 self.emit.function(*n_vars, symbol.as_str())
             }
             CommandDetails::Return => self.emit._return(),
             CommandDetails::Call { n_args, symbol } => self.emit.call(*n_args, symbol.as_str()),
         }
+
+        Ok(())
     }
 }
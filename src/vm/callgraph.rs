@@ -0,0 +1,83 @@
+//! Which functions of a whole program can ever be called, so the rest can be left out.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::VmFile;
+use crate::transformer::CommandDetails;
+
+/// The functions each function calls, across every file of a program
+#[derive(Clone, Debug, Default)]
+pub struct CallGraph {
+    calls: BTreeMap<String, BTreeSet<String>>,
+    /// Functions called by code outside of any function, which runs when execution falls into it
+    top_level_calls: BTreeSet<String>,
+}
+
+impl CallGraph {
+    pub fn build(files: &[VmFile]) -> CallGraph {
+        let mut graph = CallGraph::default();
+
+        for file in files {
+            let mut function: Option<&str> = None;
+            for (command, _) in file.commands.iter() {
+                match command {
+                    CommandDetails::Function { symbol, .. } => {
+                        graph.calls.entry(symbol.clone()).or_default();
+                        function = Some(symbol);
+                    }
+                    CommandDetails::Call { symbol, .. } => {
+                        let callees = match function {
+                            Some(caller) => graph.calls.entry(caller.to_string()).or_default(),
+                            None => &mut graph.top_level_calls,
+                        };
+                        callees.insert(symbol.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Every function reachable from `root` or from code outside of any function
+    pub fn reachable_from(&self, root: &str) -> BTreeSet<String> {
+        let mut reachable = BTreeSet::new();
+        let mut pending: Vec<&str> = vec![root];
+        pending.extend(self.top_level_calls.iter().map(|s| s.as_str()));
+
+        while let Some(function) = pending.pop() {
+            if !reachable.insert(function.to_string()) {
+                continue;
+            }
+            if let Some(callees) = self.calls.get(function) {
+                pending.extend(callees.iter().map(|s| s.as_str()));
+            }
+        }
+
+        reachable
+    }
+}
+
+/// Remove the functions that can not be reached from `root`.
+/// Returns the names of the removed functions in the order they were declared.
+pub fn eliminate_unreachable(files: &mut [VmFile], root: &str) -> Vec<String> {
+    let reachable = CallGraph::build(files).reachable_from(root);
+    let mut removed = Vec::new();
+
+    for file in files.iter_mut() {
+        // code before the first function is kept, it is not part of any function
+        let mut keep = true;
+        file.commands.retain(|(command, _)| {
+            if let CommandDetails::Function { symbol, .. } = command {
+                keep = reachable.contains(symbol);
+                if !keep {
+                    removed.push(symbol.clone());
+                }
+            }
+            keep
+        });
+    }
+
+    removed
+}
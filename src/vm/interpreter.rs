@@ -0,0 +1,439 @@
+//! A reference interpreter for parsed VM commands.
+//! Memory is laid out the same way the emitters lay it out on the Hack platform,
+//! so its RAM can be compared against an emulator running the translated assembly.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::VmFile;
+use crate::hack::emulator::RAM_SIZE;
+use crate::transformer::{ArithmeticType, CommandDetails, Segment};
+
+const SP: u16 = 0;
+const LCL: u16 = 1;
+const ARG: u16 = 2;
+const THIS: u16 = 3;
+const THAT: u16 = 4;
+const TEMP_BASE: u16 = 5;
+const TEMP_SIZE: i16 = 8;
+const STACK_BASE: i16 = 256;
+
+const LOGIC_TRUE: i16 = -1;
+const LOGIC_FALSE: i16 = 0;
+
+#[derive(Clone, Debug)]
+pub enum VmError {
+    /// The program could not be loaded, e.g. a jump to a label which does not exist
+    Load(String),
+    /// The program did something illegal while running
+    Runtime { pc: usize, message: String },
+}
+
+impl std::fmt::Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VmError::Load(msg) => write!(f, "VM Load Error: {}", msg),
+            VmError::Runtime { pc, message } => {
+                write!(f, "VM Runtime Error: {} at command {}", message, pc)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    /// The requested number of commands were executed
+    StepLimit,
+    /// The program jumped to itself forever
+    Halted,
+    /// Execution ran past the last command
+    EndOfProgram,
+    /// The outermost function returned, with no caller to return to
+    Returned,
+}
+
+/// A command with its jumps resolved to command indices
+#[derive(Clone, Debug)]
+enum Op {
+    Arithmetic(ArithmeticType),
+    Push(Segment, i16),
+    Pop(Segment, i16),
+    Label,
+    Goto(usize),
+    IfGoto(usize),
+    IfNotGoto(usize),
+    AddConst(i16),
+    Function { n_vars: i16 },
+    Call { n_args: i16, symbol: String },
+    Return,
+}
+
+struct Frame {
+    return_pc: usize,
+    /// RAM address the return address was pushed to
+    slot: u16,
+}
+
+pub struct Interpreter {
+    ops: Vec<Op>,
+    /// The file each op came from, as an index into `file_names`
+    op_files: Vec<usize>,
+    file_names: Vec<String>,
+    functions: HashMap<String, usize>,
+    ram: Vec<i16>,
+    statics: BTreeMap<String, i16>,
+    frames: Vec<Frame>,
+    written: BTreeSet<u16>,
+    pc: usize,
+    steps: u64,
+}
+
+impl Interpreter {
+    /// Load a program. Functions and labels are resolved across all of the files.
+    pub fn new(files: &[VmFile]) -> Result<Interpreter, VmError> {
+        let mut ops = Vec::new();
+        let mut op_files = Vec::new();
+        let mut functions = HashMap::new();
+        let mut labels = HashMap::new();
+
+        // first pass: find where every function and label starts
+        let mut index = 0;
+        for file in files.iter() {
+            let mut function: Option<&str> = None;
+            for (command, _) in file.commands.iter() {
+                match command {
+                    CommandDetails::Function { symbol, .. } => {
+                        if functions.insert(symbol.clone(), index).is_some() {
+                            return Err(VmError::Load(format!("function '{}' is declared more than once", symbol)));
+                        }
+                        function = Some(symbol.as_str());
+                    }
+                    CommandDetails::Label(label) => {
+                        let name = scoped_label(function, label);
+                        if labels.insert(name.clone(), index).is_some() {
+                            return Err(VmError::Load(format!("label '{}' is declared more than once", name)));
+                        }
+                    }
+                    _ => {}
+                }
+                index += 1;
+            }
+        }
+
+        // second pass: resolve jumps
+        for (file_index, file) in files.iter().enumerate() {
+            let mut function: Option<&str> = None;
+            for (command, _) in file.commands.iter() {
+                let resolve = |label: &str| -> Result<usize, VmError> {
+                    let name = scoped_label(function, label);
+                    labels.get(&name).copied().ok_or_else(|| {
+                        VmError::Load(format!("label '{}' is not declared", name))
+                    })
+                };
+
+                let op = match command {
+                    CommandDetails::Arithmetic(t) => Op::Arithmetic(*t),
+                    CommandDetails::Push(segment, offset) => Op::Push(*segment, *offset),
+                    CommandDetails::Pop(segment, offset) => Op::Pop(*segment, *offset),
+                    CommandDetails::Label(_) => Op::Label,
+                    CommandDetails::Goto(label) => Op::Goto(resolve(label)?),
+                    CommandDetails::IfGoto(label) => Op::IfGoto(resolve(label)?),
+                    CommandDetails::IfNotGoto(label) => Op::IfNotGoto(resolve(label)?),
+                    CommandDetails::AddConst(value) => Op::AddConst(*value),
+                    CommandDetails::Function { n_vars, symbol } => {
+                        function = Some(symbol.as_str());
+                        Op::Function { n_vars: *n_vars }
+                    }
+                    CommandDetails::Call { n_args, symbol } => Op::Call {
+                        n_args: *n_args,
+                        symbol: symbol.clone(),
+                    },
+                    CommandDetails::Return => Op::Return,
+                };
+                ops.push(op);
+                op_files.push(file_index);
+            }
+        }
+
+        Ok(Interpreter {
+            ops,
+            op_files,
+            file_names: files.iter().map(|f| f.name.clone()).collect(),
+            functions,
+            ram: vec![0; RAM_SIZE],
+            statics: BTreeMap::new(),
+            frames: Vec::new(),
+            written: BTreeSet::new(),
+            pc: 0,
+            steps: 0,
+        })
+    }
+
+    /// Index of the next command to execute, counting across all files in load order
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn ram(&self) -> &[i16] {
+        &self.ram
+    }
+
+    pub fn peek(&self, address: u16) -> i16 {
+        self.ram[address as usize % RAM_SIZE]
+    }
+
+    pub fn poke(&mut self, address: u16, value: i16) {
+        self.ram[address as usize % RAM_SIZE] = value;
+    }
+
+    /// Static variables by their assembly symbol, e.g. `Foo.0`, in order of the symbols
+    pub fn statics(&self) -> &BTreeMap<String, i16> {
+        &self.statics
+    }
+
+    /// Every RAM address the program has written to
+    pub fn written(&self) -> &BTreeSet<u16> {
+        &self.written
+    }
+
+    /// RAM addresses currently holding a return address.
+    /// The interpreter stores command indices there, so they never match a translated program.
+    pub fn return_address_slots(&self) -> impl Iterator<Item = u16> + '_ {
+        self.frames.iter().map(|f| f.slot)
+    }
+
+    /// Set up the stack and segment pointers and call `Sys.init`, as the bootstrap code does
+    pub fn bootstrap(&mut self) -> Result<(), VmError> {
+        self.poke(SP, STACK_BASE);
+        self.poke(LCL, -1);
+        self.poke(ARG, -2);
+        self.poke(THIS, -3);
+        self.poke(THAT, -4);
+
+        self.call("Sys.init", 0, self.ops.len())
+    }
+
+    /// Run for at most `max_steps` commands
+    pub fn run(&mut self, max_steps: u64) -> Result<StopReason, VmError> {
+        for _ in 0..max_steps {
+            if let Some(reason) = self.step()? {
+                return Ok(reason);
+            }
+        }
+
+        Ok(StopReason::StepLimit)
+    }
+
+    /// Execute one command. Returns why the program stopped, if it did.
+    pub fn step(&mut self) -> Result<Option<StopReason>, VmError> {
+        let op = match self.ops.get(self.pc) {
+            Some(op) => op.clone(),
+            None => return Ok(Some(StopReason::EndOfProgram)),
+        };
+        self.steps += 1;
+
+        let mut next = self.pc + 1;
+        match op {
+            Op::Arithmetic(t) => self.arithmetic(t)?,
+            Op::Push(segment, offset) => {
+                let value = self.read_segment(segment, offset)?;
+                self.push(value)?;
+            }
+            Op::Pop(segment, offset) => {
+                let value = self.pop()?;
+                self.write_segment(segment, offset, value)?;
+            }
+            Op::Label => {}
+            Op::Goto(target) => {
+                // `label L; goto L` is how a VM program halts
+                if target <= self.pc && self.ops[target..self.pc].iter().all(|op| matches!(op, Op::Label)) {
+                    return Ok(Some(StopReason::Halted));
+                }
+                next = target;
+            }
+            Op::IfGoto(target) => {
+                if self.pop()? != 0 {
+                    next = target;
+                }
+            }
+            Op::IfNotGoto(target) => {
+                if self.pop()? != LOGIC_TRUE {
+                    next = target;
+                }
+            }
+            Op::AddConst(value) => {
+                let x = self.pop()?;
+                self.push(x.wrapping_add(value))?;
+            }
+            Op::Function { n_vars } => {
+                for _ in 0..n_vars {
+                    self.push(0)?;
+                }
+            }
+            Op::Call { n_args, symbol } => {
+                self.call(symbol.as_str(), n_args, next)?;
+                next = self.pc;
+            }
+            Op::Return => {
+                let end_frame = self.peek(LCL);
+                let return_value = self.pop()?;
+                let arg = self.peek(ARG);
+                self.write(arg as u16, return_value);
+                self.write(SP, arg.wrapping_add(1));
+                for (i, pointer) in [THAT, THIS, ARG, LCL].into_iter().enumerate() {
+                    let saved = self.peek(end_frame.wrapping_sub(i as i16 + 1) as u16);
+                    self.write(pointer, saved);
+                }
+
+                match self.frames.pop() {
+                    Some(frame) => next = frame.return_pc,
+                    None => return Ok(Some(StopReason::Returned)),
+                }
+            }
+        }
+
+        self.pc = next;
+        Ok(None)
+    }
+
+    fn error(&self, message: String) -> VmError {
+        VmError::Runtime { pc: self.pc, message }
+    }
+
+    fn write(&mut self, address: u16, value: i16) {
+        self.poke(address, value);
+        self.written.insert(address % RAM_SIZE as u16);
+    }
+
+    fn push(&mut self, value: i16) -> Result<(), VmError> {
+        let sp = self.peek(SP);
+        if sp < STACK_BASE {
+            return Err(self.error(format!("stack pointer {} is below the stack", sp)));
+        }
+        self.write(sp as u16, value);
+        self.write(SP, sp.wrapping_add(1));
+
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i16, VmError> {
+        let sp = self.peek(SP).wrapping_sub(1);
+        if sp < STACK_BASE {
+            return Err(self.error("stack underflow".to_string()));
+        }
+        self.write(SP, sp);
+
+        Ok(self.peek(sp as u16))
+    }
+
+    // save the caller's frame and jump to the start of a function
+    fn call(&mut self, symbol: &str, n_args: i16, return_pc: usize) -> Result<(), VmError> {
+        let target = match self.functions.get(symbol) {
+            Some(target) => *target,
+            None => return Err(self.error(format!("call to undefined function '{}'", symbol))),
+        };
+
+        let slot = self.peek(SP) as u16;
+        self.push(0)?;
+        for pointer in [LCL, ARG, THIS, THAT] {
+            let value = self.peek(pointer);
+            self.push(value)?;
+        }
+
+        let sp = self.peek(SP);
+        self.write(ARG, sp.wrapping_sub(5 + n_args));
+        self.write(LCL, sp);
+        self.frames.push(Frame { return_pc, slot });
+        self.pc = target;
+
+        Ok(())
+    }
+
+    fn arithmetic(&mut self, t: ArithmeticType) -> Result<(), VmError> {
+        let logic = |b: bool| if b { LOGIC_TRUE } else { LOGIC_FALSE };
+
+        let result = match t {
+            ArithmeticType::Neg => self.pop()?.wrapping_neg(),
+            ArithmeticType::Not => !self.pop()?,
+            _ => {
+                let y = self.pop()?;
+                let x = self.pop()?;
+                match t {
+                    ArithmeticType::Add => x.wrapping_add(y),
+                    ArithmeticType::Sub => x.wrapping_sub(y),
+                    ArithmeticType::Eq => logic(x == y),
+                    ArithmeticType::Gt => logic(x > y),
+                    ArithmeticType::Lt => logic(x < y),
+                    ArithmeticType::And => x & y,
+                    ArithmeticType::Or => x | y,
+                    ArithmeticType::Neg | ArithmeticType::Not => unreachable!(),
+                }
+            }
+        };
+
+        self.push(result)
+    }
+
+    fn static_symbol(&self, offset: i16) -> String {
+        format!("{}.{}", self.file_names[self.op_files[self.pc]], offset)
+    }
+
+    // the RAM address of a segment entry. Not used for constant and static
+    fn segment_address(&self, segment: Segment, offset: i16) -> Result<u16, VmError> {
+        let address = match segment {
+            Segment::Local => self.peek(LCL).wrapping_add(offset),
+            Segment::Argument => self.peek(ARG).wrapping_add(offset),
+            Segment::This => self.peek(THIS).wrapping_add(offset),
+            Segment::That => self.peek(THAT).wrapping_add(offset),
+            Segment::Pointer => match offset {
+                0 => THIS as i16,
+                1 => THAT as i16,
+                _ => return Err(self.error(format!("pointer {} is out of range", offset))),
+            },
+            Segment::Temp => {
+                if !(0..TEMP_SIZE).contains(&offset) {
+                    return Err(self.error(format!("temp {} is out of range", offset)));
+                }
+                TEMP_BASE as i16 + offset
+            }
+            Segment::Constant | Segment::Static => unreachable!("{:?} is not stored in RAM", segment),
+        };
+
+        Ok(address as u16)
+    }
+
+    fn read_segment(&self, segment: Segment, offset: i16) -> Result<i16, VmError> {
+        match segment {
+            Segment::Constant => Ok(offset),
+            Segment::Static => Ok(*self.statics.get(&self.static_symbol(offset)).unwrap_or(&0)),
+            _ => Ok(self.peek(self.segment_address(segment, offset)?)),
+        }
+    }
+
+    fn write_segment(&mut self, segment: Segment, offset: i16, value: i16) -> Result<(), VmError> {
+        match segment {
+            Segment::Constant => Err(self.error("cannot pop to constant".to_string())),
+            Segment::Static => {
+                self.statics.insert(self.static_symbol(offset), value);
+                Ok(())
+            }
+            _ => {
+                let address = self.segment_address(segment, offset)?;
+                self.write(address, value);
+                Ok(())
+            }
+        }
+    }
+}
+
+// labels are scoped to their function, matching the names the writer emits. Labels outside of
+// any function are left as they are
+fn scoped_label(function: Option<&str>, label: &str) -> String {
+    match function {
+        Some(function) => format!("{}${}", function, label),
+        None => label.to_string(),
+    }
+}
@@ -0,0 +1,65 @@
+//! Whole VM programs: parsed from every file, analysed, and executed directly without
+//! translating them to assembly first.
+
+pub mod interpreter;
+pub mod callgraph;
+pub mod optimize;
+
+use std::path::Path;
+
+use crate::sources::{find_vm_files, Discovery, Source};
+use crate::transformer::diagnostic::Diagnostic;
+use crate::transformer::{CommandDetails, TransformError, TransformResult};
+use crate::transformer::transform::parse_file;
+
+/// The parsed commands of one `.vm` file
+#[derive(Clone, Debug)]
+pub struct VmFile {
+    /// The file stem, e.g. `Foo` for `Foo.vm`. Statics are scoped to it.
+    pub name: String,
+    /// Each command with the source line it was parsed from
+    pub commands: Vec<(CommandDetails, String)>,
+}
+
+impl VmFile {
+    /// Read and parse a `.vm` file
+    pub fn parse(path: &Path) -> TransformResult<VmFile> {
+        let name = path.file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| TransformError::IoError(format!("file name of '{}' is not valid unicode", path.display())))?
+            .to_string();
+        let file_in = std::fs::File::open(path)
+            .map_err(|e| TransformError::IoError(format!("failed to read '{}': {}", path.display(), e)))?;
+
+        let commands = parse_file(file_in, path, &mut Vec::new())?;
+
+        Ok(VmFile { name, commands })
+    }
+
+    /// Parse a source held in memory, adding any warnings to `warnings`
+    pub fn from_source(source: &Source, warnings: &mut Vec<Diagnostic>) -> TransformResult<VmFile> {
+        let commands = parse_file(source.text.as_bytes(), Path::new(&source.name), warnings)?;
+
+        Ok(VmFile {
+            name: source.stem().to_string(),
+            commands,
+        })
+    }
+}
+
+/// Whether any of the files declares the function
+pub fn defines_function(files: &[VmFile], symbol: &str) -> bool {
+    files.iter()
+        .flat_map(|file| file.commands.iter())
+        .any(|(command, _)| matches!(command, CommandDetails::Function { symbol: s, .. } if s == symbol))
+}
+
+/// Parse a `.vm` file, or the `.vm` files found in a directory
+pub fn parse_path(path: &Path, discovery: &Discovery) -> TransformResult<Vec<VmFile>> {
+    let paths = find_vm_files(path, discovery)
+        .map_err(|e| TransformError::IoError(format!("failed to read '{}': {}", path.display(), e)))?;
+
+    paths.iter()
+        .map(|p| VmFile::parse(p))
+        .collect()
+}
@@ -0,0 +1,220 @@
+//! A peephole optimizer over the parsed commands of a program.
+//! Short runs of straight-line commands are matched against a table of rules and rewritten
+//! into fewer or cheaper commands, before any code is written.
+
+use super::VmFile;
+use crate::transformer::{ArithmeticType, CommandDetails, Segment};
+
+/// How hard the optimizer tries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Translate the commands as written
+    #[default]
+    None,
+    /// Fold constant arithmetic and drop commands that cancel out
+    Basic,
+    /// Also fuse common sequences into single commands that the emitters lower specially
+    Full,
+}
+
+impl OptLevel {
+    pub const ALL: &'static [OptLevel] = &[OptLevel::None, OptLevel::Basic, OptLevel::Full];
+
+    /// The level as it is given on the command line, `0` to `2`
+    pub fn from_number(number: &str) -> Option<OptLevel> {
+        let number: usize = number.parse().ok()?;
+        OptLevel::ALL.get(number).copied()
+    }
+}
+
+struct Rule {
+    /// The lowest level the rule is applied at
+    level: OptLevel,
+    /// Number of commands the rule looks at
+    window: usize,
+    /// The commands to replace the window with, or None if the rule does not match
+    rewrite: fn(&[CommandDetails]) -> Option<Vec<CommandDetails>>,
+}
+
+/// Rules are tried in order at each command, the first one that matches is applied
+const RULES: &[Rule] = &[
+    Rule { level: OptLevel::Basic, window: 3, rewrite: fold_binary },
+    Rule { level: OptLevel::Basic, window: 2, rewrite: fold_unary },
+    Rule { level: OptLevel::Basic, window: 2, rewrite: push_pop },
+    Rule { level: OptLevel::Basic, window: 2, rewrite: add_zero },
+    Rule { level: OptLevel::Full, window: 2, rewrite: add_const },
+    Rule { level: OptLevel::Full, window: 2, rewrite: merge_add_const },
+    Rule { level: OptLevel::Full, window: 2, rewrite: fold_add_const },
+    Rule { level: OptLevel::Full, window: 2, rewrite: if_not_goto },
+];
+
+/// Optimize every file of a program. Returns the number of rewrites applied.
+pub fn optimize_program(files: &mut [VmFile], level: OptLevel) -> usize {
+    files.iter_mut()
+        .map(|file| optimize(&mut file.commands, level))
+        .sum()
+}
+
+/// Rewrite the commands until no rule matches anymore. Returns the number of rewrites applied.
+///
+/// A rewritten command keeps the source lines of every command it replaced, joined with `; `,
+/// so the comments in the generated code still show what was written.
+pub fn optimize(commands: &mut Vec<(CommandDetails, String)>, level: OptLevel) -> usize {
+    let rules: Vec<&Rule> = RULES.iter().filter(|rule| rule.level <= level).collect();
+    let mut rewrites = 0;
+
+    loop {
+        let mut changed = false;
+        let mut out = Vec::with_capacity(commands.len());
+        let mut i = 0;
+
+        while i < commands.len() {
+            match rewrite_at(&rules, &commands[i..]) {
+                Some((window, replacement)) => {
+                    let source = commands[i..i + window].iter()
+                        .map(|(_, source)| source.trim())
+                        .collect::<Vec<_>>()
+                        .join("; ");
+                    out.extend(replacement.into_iter().map(|command| (command, source.clone())));
+                    i += window;
+                    rewrites += 1;
+                    changed = true;
+                }
+                None => {
+                    out.push(commands[i].clone());
+                    i += 1;
+                }
+            }
+        }
+
+        *commands = out;
+        if !changed {
+            return rewrites;
+        }
+    }
+}
+
+// the first rule that matches the commands starting at the front of `commands`
+fn rewrite_at(rules: &[&Rule], commands: &[(CommandDetails, String)]) -> Option<(usize, Vec<CommandDetails>)> {
+    for rule in rules {
+        if commands.len() < rule.window {
+            continue;
+        }
+        let window: Vec<CommandDetails> = commands[..rule.window].iter()
+            .map(|(command, _)| command.clone())
+            .collect();
+        if let Some(replacement) = (rule.rewrite)(&window) {
+            return Some((rule.window, replacement));
+        }
+    }
+
+    None
+}
+
+fn push_constant(value: i16) -> CommandDetails {
+    CommandDetails::Push(Segment::Constant, value)
+}
+
+// `push constant a; push constant b; add` -> `push constant a+b`
+fn fold_binary(window: &[CommandDetails]) -> Option<Vec<CommandDetails>> {
+    let (x, y, op) = match window {
+        [CommandDetails::Push(Segment::Constant, x), CommandDetails::Push(Segment::Constant, y), CommandDetails::Arithmetic(op)] => (*x, *y, *op),
+        _ => return None,
+    };
+    let logic = |b: bool| if b { -1 } else { 0 };
+
+    // the emitters compare by subtracting, one way or the other, so when that overflows the
+    // program computes something else than the true comparison. Leave it to them
+    let overflows = x.checked_sub(y).is_none() || y.checked_sub(x).is_none();
+    if overflows && matches!(op, ArithmeticType::Gt | ArithmeticType::Lt) {
+        return None;
+    }
+
+    let value = match op {
+        ArithmeticType::Add => x.wrapping_add(y),
+        ArithmeticType::Sub => x.wrapping_sub(y),
+        ArithmeticType::Eq => logic(x == y),
+        ArithmeticType::Gt => logic(x > y),
+        ArithmeticType::Lt => logic(x < y),
+        ArithmeticType::And => x & y,
+        ArithmeticType::Or => x | y,
+        ArithmeticType::Neg | ArithmeticType::Not => return None,
+    };
+
+    Some(vec![push_constant(value)])
+}
+
+// `push constant 0; not` -> `push constant -1`
+fn fold_unary(window: &[CommandDetails]) -> Option<Vec<CommandDetails>> {
+    match window {
+        [CommandDetails::Push(Segment::Constant, x), CommandDetails::Arithmetic(ArithmeticType::Neg)] => {
+            Some(vec![push_constant(x.wrapping_neg())])
+        }
+        [CommandDetails::Push(Segment::Constant, x), CommandDetails::Arithmetic(ArithmeticType::Not)] => {
+            Some(vec![push_constant(!x)])
+        }
+        _ => None,
+    }
+}
+
+// `push local 0; pop local 0` does nothing
+fn push_pop(window: &[CommandDetails]) -> Option<Vec<CommandDetails>> {
+    match window {
+        [CommandDetails::Push(a, i), CommandDetails::Pop(b, j)] if a == b && i == j => Some(Vec::new()),
+        _ => None,
+    }
+}
+
+// `push constant 0; add` does nothing
+fn add_zero(window: &[CommandDetails]) -> Option<Vec<CommandDetails>> {
+    match window {
+        [CommandDetails::Push(Segment::Constant, 0), CommandDetails::Arithmetic(ArithmeticType::Add | ArithmeticType::Sub)] => {
+            Some(Vec::new())
+        }
+        _ => None,
+    }
+}
+
+// `push constant 1; add` -> `add-const 1`, `push constant 1; sub` -> `add-const -1`
+fn add_const(window: &[CommandDetails]) -> Option<Vec<CommandDetails>> {
+    match window {
+        [CommandDetails::Push(Segment::Constant, x), CommandDetails::Arithmetic(ArithmeticType::Add)] => {
+            Some(vec![CommandDetails::AddConst(*x)])
+        }
+        [CommandDetails::Push(Segment::Constant, x), CommandDetails::Arithmetic(ArithmeticType::Sub)] => {
+            Some(vec![CommandDetails::AddConst(x.wrapping_neg())])
+        }
+        _ => None,
+    }
+}
+
+// `add-const a; add-const b` -> `add-const a+b`
+fn merge_add_const(window: &[CommandDetails]) -> Option<Vec<CommandDetails>> {
+    match window {
+        [CommandDetails::AddConst(x), CommandDetails::AddConst(y)] => match x.wrapping_add(*y) {
+            0 => Some(Vec::new()),
+            sum => Some(vec![CommandDetails::AddConst(sum)]),
+        },
+        _ => None,
+    }
+}
+
+// `push constant a; add-const b` -> `push constant a+b`
+fn fold_add_const(window: &[CommandDetails]) -> Option<Vec<CommandDetails>> {
+    match window {
+        [CommandDetails::Push(Segment::Constant, x), CommandDetails::AddConst(y)] => {
+            Some(vec![push_constant(x.wrapping_add(*y))])
+        }
+        _ => None,
+    }
+}
+
+// `not; if-goto L` -> `if-not-goto L`
+fn if_not_goto(window: &[CommandDetails]) -> Option<Vec<CommandDetails>> {
+    match window {
+        [CommandDetails::Arithmetic(ArithmeticType::Not), CommandDetails::IfGoto(label)] => {
+            Some(vec![CommandDetails::IfNotGoto(label.clone())])
+        }
+        _ => None,
+    }
+}
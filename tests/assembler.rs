@@ -0,0 +1,96 @@
+//! The assembler turns Hack assembly into the machine code of the nand2tetris assembler
+
+use vm_translator::hack::assembler::assemble;
+
+// the machine code of a program as `.hack` text
+fn hack(asm: &str) -> String {
+    assemble(asm).unwrap().to_hack_text()
+}
+
+// the error of assembling a program that is expected to fail
+fn error(asm: &str) -> String {
+    match assemble(asm) {
+        Ok(_) => panic!("'{}' assembled", asm),
+        Err(error) => error.to_string(),
+    }
+}
+
+#[test]
+fn encodes_a_and_c_instructions() {
+    let asm = "@2\nD=A\n@3\nD=D+A\n@0\nM=D\n";
+    let expected = "\
+        0000000000000010\n\
+        1110110000010000\n\
+        0000000000000011\n\
+        1110000010010000\n\
+        0000000000000000\n\
+        1110001100001000\n";
+
+    assert_eq!(hack(asm), expected);
+}
+
+#[test]
+fn encodes_destinations_and_jumps() {
+    assert_eq!(hack("AMD=M+1;JMP"), "1111110111111111\n");
+    assert_eq!(hack("0;JMP"), "1110101010000111\n");
+    assert_eq!(hack("D;JGT"), "1110001100000001\n");
+    assert_eq!(hack("MD=-1"), "1110111010011000\n");
+}
+
+#[test]
+fn commuted_operands_encode_like_the_canonical_ones() {
+    assert_eq!(hack("D=A+D"), hack("D=D+A"));
+    assert_eq!(hack("M=M|D"), hack("M=D|M"));
+}
+
+#[test]
+fn predefined_symbols_have_their_addresses() {
+    let program = assemble("@SP\n@LCL\n@ARG\n@THIS\n@THAT\n@R13\n@SCREEN\n@KBD\n").unwrap();
+
+    assert_eq!(program.rom, vec![0, 1, 2, 3, 4, 13, 0x4000, 0x6000]);
+}
+
+#[test]
+fn labels_are_the_address_of_the_next_instruction() {
+    let program = assemble("// start\n@END\n0;JMP\n(LOOP)\n\n(END)\n@LOOP\n0;JMP\n").unwrap();
+
+    assert_eq!(program.symbols["LOOP"], 2);
+    assert_eq!(program.symbols["END"], 2);
+    assert_eq!(program.rom[0], 2);
+}
+
+#[test]
+fn variables_are_allocated_from_16_in_order_of_first_use() {
+    let program = assemble("@i\n@sum\n@i\n@Main.0\n").unwrap();
+
+    assert_eq!(program.rom, vec![16, 17, 16, 18]);
+}
+
+#[test]
+fn labels_used_before_they_are_declared_are_not_variables() {
+    let program = assemble("@x\n@LATER\n(LATER)\n@y\n").unwrap();
+
+    assert_eq!(program.rom, vec![16, 2, 17]);
+}
+
+#[test]
+fn invalid_lines_are_reported_with_their_line_number() {
+    assert_eq!(error("@1\n\nM=M-2\n"), "Assembler Error: invalid computation 'M-2' on line 3");
+    assert_eq!(error("D;JMPP"), "Assembler Error: invalid jump 'JMPP' on line 1");
+    assert_eq!(error("@32768"), "Assembler Error: invalid constant '32768' on line 1");
+    assert_eq!(error("@1x"), "Assembler Error: invalid constant '1x' on line 1");
+    assert_eq!(error("(L\n"), "Assembler Error: unterminated label '(L' on line 1");
+}
+
+#[test]
+fn label_declared_twice_is_an_error() {
+    assert_eq!(
+        error("(L)\n@L\n(L)\n"),
+        "Assembler Error: symbol 'L' is declared more than once on line 3"
+    );
+}
+
+#[test]
+fn predefined_symbol_can_not_be_a_label() {
+    assert!(error("(SP)\n").contains("declared more than once"));
+}
@@ -0,0 +1,70 @@
+//! Bootstrap code that calls `Sys.init` is emitted when the program declares it, unless told otherwise
+
+use vm_translator::{translate_to, Bootstrap, Options, Report, Source};
+
+const SYS: &str = "function Sys.init 0\nlabel END\ngoto END\n";
+const MAIN: &str = "function Main.main 0\npush constant 1\nreturn\n";
+
+fn translate(files: &[(&str, &str)], bootstrap: Bootstrap) -> Report {
+    let sources: Vec<Source> = files.iter().map(|(name, text)| Source::new(*name, *text)).collect();
+    let options = Options { bootstrap, ..Options::default() };
+
+    translate_to(&sources, &options, Vec::new()).unwrap()
+}
+
+#[test]
+fn auto_emits_the_bootstrap_when_sys_init_is_declared() {
+    let report = translate(&[("Main.vm", MAIN), ("Sys.vm", SYS)], Bootstrap::Auto);
+
+    assert!(report.bootstrap);
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+}
+
+#[test]
+fn auto_leaves_out_the_bootstrap_without_sys_init() {
+    let report = translate(&[("Main.vm", MAIN)], Bootstrap::Auto);
+
+    assert!(!report.bootstrap);
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+}
+
+#[test]
+fn never_leaves_out_the_bootstrap_even_with_sys_init() {
+    let report = translate(&[("Sys.vm", SYS)], Bootstrap::Never);
+
+    assert!(!report.bootstrap);
+}
+
+#[test]
+fn always_emits_the_bootstrap() {
+    let report = translate(&[("Sys.vm", SYS)], Bootstrap::Always);
+
+    assert!(report.bootstrap);
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+}
+
+#[test]
+fn always_without_sys_init_warns() {
+    let report = translate(&[("Main.vm", MAIN)], Bootstrap::Always);
+
+    assert!(report.bootstrap);
+    assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+    let warning = report.warnings[0].to_string();
+    assert!(warning.contains("bootstrap code calls Sys.init, but no source declares it"), "{}", warning);
+}
+
+#[test]
+fn bootstrap_is_what_calls_sys_init() {
+    let mut with = Vec::new();
+    let mut without = Vec::new();
+    let sources = [Source::new("Sys.vm", SYS)];
+    translate_to(&sources, &Options { bootstrap: Bootstrap::Always, ..Options::default() }, &mut with).unwrap();
+    translate_to(&sources, &Options { bootstrap: Bootstrap::Never, ..Options::default() }, &mut without).unwrap();
+
+    let calls = |asm: &[u8]| String::from_utf8_lossy(asm)
+        .lines()
+        .filter(|line| line.split_whitespace().next() == Some("@Sys.init"))
+        .count();
+    assert!(calls(&with) > 0);
+    assert_eq!(calls(&without), 0);
+}
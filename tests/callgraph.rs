@@ -0,0 +1,100 @@
+//! Functions that can not be reached from `Sys.init` are left out of the program
+
+use vm_translator::transformer::CommandDetails;
+use vm_translator::vm::callgraph::{eliminate_unreachable, CallGraph};
+use vm_translator::vm::VmFile;
+use vm_translator::{translate_to, Options, Source};
+
+fn files(sources: &[(&str, &str)]) -> Vec<VmFile> {
+    sources.iter()
+        .map(|(name, text)| VmFile::from_source(&Source::new(*name, *text), &mut Vec::new()).unwrap())
+        .collect()
+}
+
+// the functions still declared in the files
+fn declared(files: &[VmFile]) -> Vec<String> {
+    files.iter()
+        .flat_map(|file| file.commands.iter())
+        .filter_map(|(command, _)| match command {
+            CommandDetails::Function { symbol, .. } => Some(symbol.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+const SYS: &str = "function Sys.init 0\ncall Main.main 0\nlabel END\ngoto END\n";
+
+#[test]
+fn function_only_called_by_an_unreachable_function_is_removed() {
+    let main = "function Main.main 0\npush constant 0\nreturn\n\
+                function Main.unused 0\ncall Main.helper 0\nreturn\n\
+                function Main.helper 0\npush constant 1\nreturn\n";
+    let mut files = files(&[("Sys.vm", SYS), ("Main.vm", main)]);
+
+    let removed = eliminate_unreachable(&mut files, "Sys.init");
+
+    assert_eq!(removed, ["Main.unused", "Main.helper"]);
+    assert_eq!(declared(&files), ["Sys.init", "Main.main"]);
+    // every command of the removed functions goes with them
+    assert_eq!(files[1].commands.len(), 3);
+}
+
+#[test]
+fn recursive_functions_are_kept_only_if_reachable() {
+    let main = "function Main.main 0\npush constant 3\ncall Main.main 1\nreturn\n\
+                function Main.even 0\ncall Main.odd 0\nreturn\n\
+                function Main.odd 0\ncall Main.even 0\nreturn\n";
+    let mut files = files(&[("Sys.vm", SYS), ("Main.vm", main)]);
+
+    let removed = eliminate_unreachable(&mut files, "Sys.init");
+
+    assert_eq!(removed, ["Main.even", "Main.odd"]);
+    assert_eq!(declared(&files), ["Sys.init", "Main.main"]);
+}
+
+#[test]
+fn call_to_an_undefined_function_is_not_an_error() {
+    let sys = "function Sys.init 0\ncall Missing.f 0\nlabel END\ngoto END\n";
+    let mut files = files(&[("Sys.vm", sys), ("Main.vm", "function Main.main 0\nreturn\n")]);
+
+    let reachable = CallGraph::build(&files).reachable_from("Sys.init");
+    assert!(reachable.contains("Missing.f"));
+
+    let removed = eliminate_unreachable(&mut files, "Sys.init");
+    assert_eq!(removed, ["Main.main"]);
+    assert_eq!(declared(&files), ["Sys.init"]);
+}
+
+#[test]
+fn functions_called_outside_of_any_function_are_reachable() {
+    let main = "call Main.main 0\nfunction Main.main 0\nreturn\nfunction Main.unused 0\nreturn\n";
+    let mut files = files(&[("Main.vm", main), ("Sys.vm", "function Sys.init 0\nreturn\n")]);
+
+    let removed = eliminate_unreachable(&mut files, "Sys.init");
+
+    assert_eq!(removed, ["Main.unused"]);
+    assert!(matches!(&files[0].commands[0].0, CommandDetails::Call { symbol, .. } if symbol == "Main.main"));
+}
+
+#[test]
+fn removed_functions_are_reported_unless_kept() {
+    let sources = [
+        Source::new("Sys.vm", SYS),
+        Source::new("Main.vm", "function Main.main 0\nreturn\nfunction Main.unused 0\nreturn\n"),
+    ];
+
+    let report = translate_to(&sources, &Options::default(), Vec::new()).unwrap();
+    assert_eq!(report.removed_functions, ["Main.unused"]);
+
+    let options = Options { keep_unreachable: true, ..Options::default() };
+    let report = translate_to(&sources, &options, Vec::new()).unwrap();
+    assert!(report.removed_functions.is_empty());
+}
+
+#[test]
+fn nothing_is_removed_without_sys_init() {
+    let sources = [Source::new("Main.vm", "function Main.main 0\nreturn\nfunction Main.unused 0\nreturn\n")];
+    let report = translate_to(&sources, &Options::default(), Vec::new()).unwrap();
+
+    assert!(report.removed_functions.is_empty());
+}
@@ -0,0 +1,299 @@
+//! Runs the translator binary on small programs written to a temporary folder
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// a fresh folder holding the given .vm files
+fn program(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("vm_translator_{}", std::process::id()))
+        .join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (file_name, source) in files {
+        fs::write(dir.join(file_name), source).unwrap();
+    }
+
+    dir
+}
+
+// translate a folder and return the assembly written to `folder/folder.asm`
+fn translate(dir: &PathBuf) -> String {
+    let status = Command::new(env!("CARGO_BIN_EXE_vm_translator"))
+        .arg(dir)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "translating {} failed", dir.display());
+
+    let name = dir.file_name().unwrap();
+    fs::read_to_string(dir.join(name).with_extension("asm")).unwrap()
+}
+
+#[test]
+fn labels_are_unique_across_files() {
+    let util = "function Util.cmp 0\npush argument 0\npush argument 1\neq\nreturn\n";
+    let caller = |class: &str| format!(
+        "function {}.f 0\npush constant 1\npush constant 2\nlt\ncall Util.cmp 2\nreturn\n",
+        class,
+    );
+    let dir = program("Labels", &[
+        ("A.vm", &caller("A")),
+        ("B.vm", &caller("B")),
+        ("Util.vm", util),
+    ]);
+
+    let asm = translate(&dir);
+    let mut labels = HashSet::new();
+    for line in asm.lines().map(str::trim).filter(|line| line.starts_with('(')) {
+        assert!(labels.insert(line.to_string()), "label {} is declared more than once", line);
+    }
+}
+
+#[test]
+fn source_that_is_not_utf8_is_a_located_error() {
+    let dir = program("NotUtf8", &[]);
+    fs::write(dir.join("Main.vm"), b"push constant 1\npush \xff\xfe 2\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_vm_translator"))
+        .args(["check".as_ref(), dir.as_os_str()])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("source is not valid utf-8"), "{}", stderr);
+    assert!(stderr.contains("Main.vm:2:6"), "{}", stderr);
+}
+
+/// What running the binary printed and how it exited
+struct Run {
+    code: Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+fn run(args: &[&str]) -> Run {
+    let output = Command::new(env!("CARGO_BIN_EXE_vm_translator"))
+        .args(args)
+        .output()
+        .unwrap();
+
+    Run {
+        code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+// a program with Sys.init that leaves 15 in RAM[5], as `dir/Sys.vm`
+fn sys_program(name: &str) -> PathBuf {
+    program(name, &[("Sys.vm", "function Sys.init 0\npush constant 7\npush constant 8\nadd\npop temp 0\nlabel END\ngoto END\n")])
+}
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[test]
+fn help_prints_usage() {
+    let run = run(&["help"]);
+
+    assert_eq!(run.code, Some(0));
+    assert!(run.stdout.contains("Usage:"), "{}", run.stdout);
+    assert!(run.stdout.contains("Exit codes:"), "{}", run.stdout);
+}
+
+#[test]
+fn missing_path_is_a_usage_error() {
+    let run = run(&[]);
+
+    assert_eq!(run.code, Some(2));
+    assert!(run.stderr.contains("A file or folder must be supplied."), "{}", run.stderr);
+}
+
+#[test]
+fn unknown_option_is_a_usage_error() {
+    for args in [&["translate", "X.vm", "--frobnicate"][..], &["check", "X.vm", "-o", "-"], &["run", "X.vm", "--cycles", "many"]] {
+        let run = run(args);
+
+        assert_eq!(run.code, Some(2), "{:?}: {}", args, run.stderr);
+        assert!(run.stderr.contains("Run 'vm_translator help' for usage."), "{}", run.stderr);
+    }
+}
+
+#[test]
+fn stdin_as_input_is_a_usage_error() {
+    let run = run(&["translate", "-"]);
+
+    assert_eq!(run.code, Some(2));
+    assert!(run.stderr.contains("can not be read from stdin"), "{}", run.stderr);
+}
+
+#[test]
+fn missing_file_is_an_io_error() {
+    let run = run(&["check", "Missing.vm"]);
+
+    assert_eq!(run.code, Some(3));
+    assert!(run.stderr.contains("IO Error: failed to read 'Missing.vm'"), "{}", run.stderr);
+}
+
+#[test]
+fn errors_in_vm_code_fail() {
+    let dir = program("Broken", &[("Main.vm", "push constant 1\npussh constant 2\n")]);
+    let run = run(&["check", dir.to_str().unwrap()]);
+
+    assert_eq!(run.code, Some(1));
+    assert!(run.stderr.contains("unknown command 'pussh'"), "{}", run.stderr);
+}
+
+#[test]
+fn assembly_that_does_not_assemble_is_an_internal_error() {
+    // over the ROM budget, which is only a warning, and too big for the assembler
+    let dir = program("TooBig", &[("Main.vm", &"push constant 1\n".repeat(6000))]);
+    let run = run(&["translate", dir.to_str().unwrap(), "--over-budget", "warn", "--emit", "hack", "-o", "-"]);
+
+    assert_eq!(run.code, Some(4), "{}", run.stderr);
+    assert!(run.stderr.contains("Internal Error: generated assembly is invalid"), "{}", run.stderr);
+}
+
+#[test]
+fn translate_writes_next_to_the_input() {
+    let dir = sys_program("Translate");
+    let run = run(&["translate", dir.to_str().unwrap()]);
+
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+    assert!(run.stdout.contains("Transforming file"), "{}", run.stdout);
+    assert!(fs::read_to_string(dir.join("Translate.asm")).unwrap().contains("(Sys.init)"));
+}
+
+#[test]
+fn output_dash_writes_to_stdout() {
+    let dir = sys_program("Stdout");
+    let run = run(&[dir.to_str().unwrap(), "-o", "-"]);
+
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+    assert!(run.stdout.contains("(Sys.init)"), "{}", run.stdout);
+    // progress goes to stderr so stdout is only the assembly
+    assert!(!run.stdout.contains("Transforming file"), "{}", run.stdout);
+    assert!(run.stderr.contains("Transforming file"), "{}", run.stderr);
+    assert!(!dir.join("Stdout.asm").exists());
+}
+
+#[test]
+fn emit_hack_writes_machine_code() {
+    let dir = sys_program("Hack");
+    let run = run(&[dir.to_str().unwrap(), "--emit", "hack", "-o", "-"]);
+
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+    assert!(!run.stdout.is_empty());
+    for line in run.stdout.lines() {
+        assert!(line.len() == 16 && line.chars().all(|c| c == '0' || c == '1'), "{}", line);
+    }
+}
+
+#[test]
+fn quiet_prints_nothing_but_errors() {
+    let dir = program("Quiet", &[("Main.vm", "function Main.f 0\npush static 300\nreturn\n")]);
+    let run = run(&[dir.to_str().unwrap(), "--quiet"]);
+
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+    assert_eq!(run.stdout, "");
+    assert_eq!(run.stderr, "");
+    assert!(dir.join("Quiet.asm").exists());
+}
+
+#[test]
+fn stats_are_printed() {
+    let dir = sys_program("Stats");
+    let run = run(&["check", dir.to_str().unwrap(), "--stats", "--quiet"]);
+
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+    assert!(run.stdout.contains("files:        1\n"), "{}", run.stdout);
+    assert!(run.stdout.contains("commands:     7\n"), "{}", run.stdout);
+    assert!(run.stdout.contains("instructions: "), "{}", run.stdout);
+    assert!(run.stdout.contains("bootstrap:    yes\n"), "{}", run.stdout);
+}
+
+#[test]
+fn init_flags_override_the_bootstrap() {
+    let with_sys = sys_program("InitSys");
+    let without_sys = program("InitNoSys", &[("Main.vm", "push constant 1\n")]);
+    let bootstrap = |dir: &PathBuf, flag: &str| {
+        let run = run(&["check", dir.to_str().unwrap(), "--stats", flag]);
+        assert_eq!(run.code, Some(0), "{}", run.stderr);
+        (run.stdout.contains("bootstrap:    yes"), run.stderr)
+    };
+
+    assert!(bootstrap(&without_sys, "--init").0);
+    assert!(!bootstrap(&with_sys, "--no-init").0);
+
+    let (_, stderr) = bootstrap(&without_sys, "--init");
+    assert!(stderr.contains("bootstrap code calls Sys.init, but no source declares it"), "{}", stderr);
+}
+
+#[test]
+fn check_reports_without_writing() {
+    let dir = sys_program("Check");
+    let run = run(&["check", dir.to_str().unwrap()]);
+
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+    assert!(run.stdout.contains("No errors in 1 files (0 warnings)"), "{}", run.stdout);
+    assert!(!dir.join("Check.asm").exists());
+}
+
+#[test]
+fn run_executes_on_the_emulator_and_the_interpreter() {
+    let dir = sys_program("Run");
+    for interpret in [false, true] {
+        let mut args = vec!["run", dir.to_str().unwrap(), "--ram", "5..6", "--cycles", "5000"];
+        if interpret {
+            args.push("--interpret");
+        }
+        let run = run(&args);
+
+        assert_eq!(run.code, Some(0), "{}", run.stderr);
+        assert!(run.stdout.contains("RAM[5] = 15"), "{}", run.stdout);
+    }
+}
+
+#[test]
+fn difftest_compares_every_backend() {
+    let run = run(&["difftest", &fixture("FibonacciElement")]);
+
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+    assert!(run.stdout.contains("[simple] matches the interpreter"), "{}", run.stdout);
+    assert!(run.stdout.contains("[compact] matches the interpreter"), "{}", run.stdout);
+}
+
+#[test]
+fn difftest_with_peephole_compares_the_halted_program() {
+    let matched = run(&["difftest", &fixture("FibonacciElement"), "--opt-level", "2", "--peephole"]);
+    assert_eq!(matched.code, Some(0), "{}", matched.stderr);
+    assert!(matched.stdout.contains("[simple] matches the interpreter"), "{}", matched.stdout);
+    assert!(matched.stdout.contains("[compact] matches the interpreter"), "{}", matched.stdout);
+
+    let unfinished = run(&["difftest", &fixture("FibonacciElement"), "--peephole", "--steps", "10"]);
+    assert_eq!(unfinished.code, Some(2));
+    assert!(unfinished.stderr.contains("the program did not halt within 10 commands"), "{}", unfinished.stderr);
+}
+
+#[test]
+fn test_scripts_pass_or_fail() {
+    let dir = sys_program("Script");
+    let script = "load Sys.asm,\noutput-file Script.out,\ncompare-to Script.cmp,\noutput-list RAM[5]%D1.6.1;\nrepeat 500 { ticktock; }\noutput;\n";
+    fs::write(dir.join("Script.tst"), script).unwrap();
+
+    fs::write(dir.join("Script.cmp"), "|RAM[5]|\n|    15|\n").unwrap();
+    let run_script = || run(&["test", dir.join("Script.tst").to_str().unwrap()]);
+    let passed = run_script();
+    assert_eq!(passed.code, Some(0), "{}", passed.stderr);
+    assert!(passed.stdout.contains("PASS"), "{}", passed.stdout);
+
+    fs::write(dir.join("Script.cmp"), "|RAM[5]|\n|    16|\n").unwrap();
+    let failed = run_script();
+    assert_eq!(failed.code, Some(1));
+    assert!(failed.stderr.contains("output differs on line 2"), "{}", failed.stderr);
+}
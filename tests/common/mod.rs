@@ -0,0 +1,14 @@
+//! Helpers shared by the integration tests
+
+use vm_translator::{TransformError, TransformResult};
+
+/// Every error of a failed translation or parse, rendered. Empty if it succeeded
+pub fn errors<T>(result: TransformResult<T>) -> Vec<String> {
+    let errors = match result {
+        Ok(_) => return Vec::new(),
+        Err(TransformError::Multiple(errors)) => errors,
+        Err(error) => vec![error],
+    };
+
+    errors.iter().map(|error| error.to_string()).collect()
+}
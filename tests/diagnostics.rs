@@ -0,0 +1,180 @@
+//! Errors in VM sources are reported with the file, line and column they are at
+
+use std::path::Path;
+
+use vm_translator::transformer::transform::parse_file;
+use vm_translator::sources::{read_sources, Discovery};
+use vm_translator::{translate, Options, Source};
+
+mod common;
+
+// the errors of parsing a source, rendered
+fn parse_errors(source: &[u8]) -> Vec<String> {
+    common::errors(parse_file(source, Path::new("Main.vm"), &mut Vec::new()))
+}
+
+#[test]
+fn invalid_utf8_is_a_located_error() {
+    let errors = parse_errors(b"push constant 1\npush \xff\xfe 2\n");
+
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("not valid utf-8"), "{}", errors[0]);
+    assert!(errors[0].contains("Main.vm:2:6"), "{}", errors[0]);
+}
+
+#[test]
+fn unknown_command_points_at_the_word() {
+    let errors = parse_errors(b"push constant 1\n  pussh constant 1\n");
+
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("unknown command 'pussh'"), "{}", errors[0]);
+    assert!(errors[0].contains("Main.vm:2:3"), "{}", errors[0]);
+    assert!(errors[0].contains("  ^^^^^"), "{}", errors[0]);
+}
+
+#[test]
+fn every_bad_line_is_reported() {
+    let source = b"add 1\npush locals 1\npush constant 40000\npush constant // none\npush constant x\n";
+    let errors = parse_errors(source);
+
+    let expected = [
+        ("unexpected '1' after command", "Main.vm:1:5"),
+        ("unknown segment 'locals'", "Main.vm:2:6"),
+        ("integer '40000' does not fit in 16 bits", "Main.vm:3:15"),
+        ("expected an integer", "Main.vm:4:15"),
+        ("expected an integer, found 'x'", "Main.vm:5:15"),
+    ];
+    assert_eq!(errors.len(), expected.len(), "{:?}", errors);
+    for (error, (message, location)) in errors.iter().zip(expected) {
+        assert!(error.contains(message), "{}", error);
+        assert!(error.contains(location), "{}", error);
+    }
+}
+
+#[test]
+fn comments_and_whitespace_separate_words() {
+    let source = b"// header\n\tpush   constant 7// seven\r\n\npush constant 1 // one\nadd\n";
+    assert!(parse_errors(source).is_empty());
+}
+
+#[test]
+fn symbols_may_use_the_whole_identifier_grammar() {
+    let source = b"function Main.f$x:y_1 0\nlabel add\nlabel _a.b$c:d9\ngoto add\ncall Main.f$x:y_1 0\nreturn\n";
+    assert!(parse_errors(source).is_empty());
+}
+
+#[test]
+fn illegal_character_in_symbol_points_at_the_character() {
+    let errors = parse_errors("function Main.f 0\nlabel a-b\nlabel café\nreturn\n".as_bytes());
+
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors[0].contains("illegal character '-' in symbol 'a-b'"), "{}", errors[0]);
+    assert!(errors[0].contains("Main.vm:2:8"), "{}", errors[0]);
+    // columns count characters, not bytes
+    assert!(errors[1].contains("illegal character 'é' in symbol 'café'"), "{}", errors[1]);
+    assert!(errors[1].contains("Main.vm:3:10"), "{}", errors[1]);
+}
+
+#[test]
+fn symbol_starting_with_a_digit_is_rejected() {
+    let errors = parse_errors(b"function Main.f 0\ngoto 1abc\ncall 12 0\nreturn\n");
+
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors[0].contains("symbol '1abc' must not start with a digit"), "{}", errors[0]);
+    assert!(errors[0].contains("Main.vm:2:6"), "{}", errors[0]);
+    assert!(errors[1].contains("expected a symbol, found '12'"), "{}", errors[1]);
+    assert!(errors[1].contains("Main.vm:3:6"), "{}", errors[1]);
+}
+
+#[test]
+fn file_name_that_can_not_name_statics_is_reported_once() {
+    let source = "push constant 1\npop static 0\npush static 0\npush static 1\n";
+    for name in ["my-prog.vm", "7x.vm"] {
+        let errors = common::errors(translate(&[Source::new(name, source)], &Options::default()));
+
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("cannot name statics"), "{}", errors[0]);
+        assert!(errors[0].contains(&format!("{}:2:5", name)), "{}", errors[0]);
+    }
+}
+
+#[test]
+fn file_name_is_only_checked_when_it_names_statics() {
+    let source = "push constant 1\npop temp 0\n";
+    assert!(translate(&[Source::new("my-prog.vm", source)], &Options::default()).is_ok());
+}
+
+#[test]
+fn every_source_that_is_not_utf8_is_reported_when_read() {
+    let dir = std::env::temp_dir().join(format!("vm_translator_utf8_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("A.vm"), b"push constant 1\npush \xff 2\n").unwrap();
+    std::fs::write(dir.join("B.vm"), b"\xfe").unwrap();
+
+    let errors = common::errors(read_sources(&dir, &Discovery::default()));
+
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors[0].contains("not valid utf-8") && errors[0].contains("A.vm:2:6"), "{}", errors[0]);
+    assert!(errors[1].contains("not valid utf-8") && errors[1].contains("B.vm:1:1"), "{}", errors[1]);
+}
+
+// the only error in a source, which has to contain the message and point at the location
+fn assert_error(source: &str, message: &str, location: &str) {
+    let errors = parse_errors(source.as_bytes());
+
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].contains(message), "{}", errors[0]);
+    assert!(errors[0].contains(location), "{}", errors[0]);
+}
+
+#[test]
+fn temp_index_past_the_segment_is_rejected() {
+    assert_error("push temp 7\npush temp 8\n", "temp index 8 is out of range 0..7", "Main.vm:2:11");
+}
+
+#[test]
+fn pointer_index_past_the_segment_is_rejected() {
+    assert_error("push pointer 1\npop pointer 2\n", "pointer index 2 is out of range 0..1", "Main.vm:2:13");
+}
+
+#[test]
+fn negative_index_is_rejected() {
+    assert_error("push local -1\n", "local index cannot be negative (-1)", "Main.vm:1:12");
+}
+
+#[test]
+fn negative_constant_is_rejected() {
+    assert_error("push constant -1\n", "constant -1 is out of range 0..32767", "Main.vm:1:15");
+}
+
+#[test]
+fn pop_to_constant_is_rejected() {
+    assert_error("push constant 1\npop constant 1\n", "cannot pop to the constant segment", "Main.vm:2:5");
+}
+
+#[test]
+fn negative_number_of_locals_is_rejected() {
+    assert_error("function Main.f -1\nreturn\n", "function cannot have a negative number of locals (-1)", "Main.vm:1:17");
+}
+
+#[test]
+fn negative_number_of_arguments_is_rejected() {
+    assert_error("function Main.f 0\ncall Main.f -2\nreturn\n", "call cannot pass a negative number of arguments (-2)", "Main.vm:2:13");
+}
+
+#[test]
+fn reserved_function_name_is_rejected() {
+    assert_error("function $$call 0\nreturn\n", "function name '$$call' is reserved", "Main.vm:1:10");
+}
+
+#[test]
+fn static_index_past_the_segment_is_a_warning() {
+    let mut warnings = Vec::new();
+    let commands = parse_file("push static 239\npush static 240\n".as_bytes(), Path::new("Main.vm"), &mut warnings).unwrap();
+
+    assert_eq!(commands.len(), 2);
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    let warning = warnings[0].to_string();
+    assert!(warning.contains("static index 240 is beyond the 240 words of the static segment"), "{}", warning);
+    assert!(warning.contains("Main.vm:2:13"), "{}", warning);
+}
@@ -0,0 +1,122 @@
+//! Every backend runs the programs in `tests/fixtures` exactly like the VM interpreter does,
+//! with either lowering and at every optimization level. With the peephole pass on, the final
+//! states are compared once the program halts
+
+use std::path::Path;
+
+use vm_translator::difftest::{check_all, DiffOptions};
+use vm_translator::sources::Discovery;
+use vm_translator::transformer::{Backend, Lowering};
+use vm_translator::vm::{self, optimize::{optimize_program, OptLevel}, VmFile};
+use vm_translator::{Bootstrap, Source};
+
+const MAX_STEPS: u64 = 100_000;
+
+fn difftest(fixture: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(fixture);
+
+    for level in [OptLevel::None, OptLevel::Basic, OptLevel::Full] {
+        let mut files = match vm::parse_path(&path, &Discovery::default()) {
+            Ok(files) => files,
+            Err(error) => panic!("{}", error),
+        };
+        optimize_program(&mut files, level);
+
+        for &lowering in Lowering::ALL {
+            for peephole in [false, true] {
+                let options = DiffOptions {
+                    emit_init: Bootstrap::Auto.resolve(&files),
+                    max_steps: MAX_STEPS,
+                    backends: Backend::ALL.to_vec(),
+                    lowering,
+                    peephole,
+                    keep: None,
+                };
+                let results = match check_all(&files, &options) {
+                    Ok(results) => results,
+                    Err(error) => panic!("{}", error),
+                };
+                for (_, divergence) in results {
+                    if let Some(divergence) = divergence {
+                        panic!(
+                            "{} at {:?} with {:?} lowering, peephole {}:\n{}",
+                            fixture, level, lowering, peephole, divergence
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn simple_add() {
+    difftest("SimpleAdd.vm");
+}
+
+#[test]
+fn stack_test() {
+    difftest("StackTest.vm");
+}
+
+#[test]
+fn segments() {
+    difftest("Segments.vm");
+}
+
+#[test]
+fn statics_are_per_file() {
+    difftest("StaticsTest");
+}
+
+#[test]
+fn fibonacci_element() {
+    difftest("FibonacciElement");
+}
+
+#[test]
+fn folding() {
+    difftest("Folding");
+}
+
+#[test]
+fn translations_are_only_written_when_kept() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let files = vm::parse_path(&fixtures.join("SimpleAdd.vm"), &Discovery::default()).unwrap();
+    let keep = std::env::temp_dir().join(format!("vm_translator_difftest_{}", std::process::id()));
+    std::fs::create_dir_all(&keep).unwrap();
+
+    let mut options = DiffOptions {
+        emit_init: false,
+        max_steps: MAX_STEPS,
+        backends: vec![Backend::Simple],
+        lowering: Lowering::Inline,
+        peephole: false,
+        keep: None,
+    };
+    assert!(check_all(&files, &options).is_ok());
+    assert!(!fixtures.join("SimpleAdd.simple.asm").exists());
+
+    options.keep = Some(keep.join("SimpleAdd.asm"));
+    assert!(check_all(&files, &options).is_ok());
+    assert!(keep.join("SimpleAdd.simple.asm").exists());
+}
+
+#[test]
+fn final_states_need_the_program_to_halt() {
+    let source = "label LOOP\npush constant 1\npop temp 0\ngoto LOOP\n";
+    let files = vec![VmFile::from_source(&Source::new("Loop.vm", source), &mut Vec::new()).unwrap()];
+    let options = DiffOptions {
+        emit_init: false,
+        max_steps: 100,
+        backends: vec![Backend::Simple],
+        lowering: Lowering::Inline,
+        peephole: true,
+        keep: None,
+    };
+
+    match check_all(&files, &options) {
+        Err(error) => assert_eq!(error.to_string(), "the program did not halt within 100 commands"),
+        Ok(_) => panic!("a program that never halts has no final state"),
+    }
+}
@@ -0,0 +1,93 @@
+//! The `.vm` files of a folder are found in a fixed order and filtered by glob patterns
+
+use std::path::{Path, PathBuf};
+
+use vm_translator::sources::{find_vm_files, Discovery};
+
+fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/discovery")
+}
+
+// the files found in the fixture folder, relative to it
+fn found(discovery: &Discovery) -> Vec<String> {
+    let root = root();
+    find_vm_files(&root, discovery).unwrap()
+        .iter()
+        .map(|path| path.strip_prefix(&root).unwrap().to_string_lossy().replace('\\', "/"))
+        .collect()
+}
+
+fn recursive() -> Discovery {
+    Discovery { recursive: true, ..Discovery::default() }
+}
+
+#[test]
+fn sys_comes_first_then_files_by_name() {
+    assert_eq!(found(&Discovery::default()), ["Sys.vm", "Main.vm", "MainTest.vm", "Zeta.vm"]);
+}
+
+#[test]
+fn recursive_search_orders_by_path() {
+    let expected = [
+        "Sys.vm", "Main.vm", "MainTest.vm", "Zeta.vm",
+        "lib/Math.vm", "lib/MathTest.vm", "lib/deep/Array.vm",
+    ];
+    assert_eq!(found(&recursive()), expected);
+}
+
+#[test]
+fn first_files_keep_their_given_order() {
+    let discovery = Discovery { first: vec!["Zeta.vm".to_string(), "Main.vm".to_string()], ..Discovery::default() };
+    assert_eq!(found(&discovery), ["Zeta.vm", "Main.vm", "MainTest.vm", "Sys.vm"]);
+}
+
+#[test]
+fn pattern_without_slash_matches_the_file_name_in_any_folder() {
+    let discovery = Discovery { exclude: vec!["*Test.vm".to_string()], ..recursive() };
+    assert_eq!(found(&discovery), ["Sys.vm", "Main.vm", "Zeta.vm", "lib/Math.vm", "lib/deep/Array.vm"]);
+
+    let discovery = Discovery { include: vec!["M*".to_string()], ..recursive() };
+    assert_eq!(found(&discovery), ["Main.vm", "MainTest.vm", "lib/Math.vm", "lib/MathTest.vm"]);
+}
+
+#[test]
+fn question_mark_matches_one_character() {
+    let discovery = Discovery { include: vec!["?ain.vm".to_string(), "Sy?.vm".to_string()], ..Discovery::default() };
+    assert_eq!(found(&discovery), ["Sys.vm", "Main.vm"]);
+}
+
+#[test]
+fn star_does_not_cross_folders() {
+    let discovery = Discovery { include: vec!["lib/*.vm".to_string()], ..recursive() };
+    assert_eq!(found(&discovery), ["lib/Math.vm", "lib/MathTest.vm"]);
+
+    let discovery = Discovery { include: vec!["*/Array.vm".to_string()], ..recursive() };
+    assert!(found(&discovery).is_empty());
+}
+
+#[test]
+fn double_star_matches_any_number_of_folders() {
+    let discovery = Discovery { include: vec!["lib/**/*.vm".to_string()], ..recursive() };
+    assert_eq!(found(&discovery), ["lib/Math.vm", "lib/MathTest.vm", "lib/deep/Array.vm"]);
+
+    let discovery = Discovery { include: vec!["**/Array.vm".to_string()], ..recursive() };
+    assert_eq!(found(&discovery), ["lib/deep/Array.vm"]);
+}
+
+#[test]
+fn exclude_wins_over_include() {
+    let discovery = Discovery {
+        include: vec!["lib/**".to_string()],
+        exclude: vec!["lib/deep/*".to_string()],
+        ..recursive()
+    };
+    assert_eq!(found(&discovery), ["lib/Math.vm", "lib/MathTest.vm"]);
+}
+
+#[test]
+fn file_named_directly_is_always_used() {
+    let path = root().join("MainTest.vm");
+    let discovery = Discovery { exclude: vec!["*".to_string()], ..Discovery::default() };
+
+    assert_eq!(find_vm_files(&path, &discovery).unwrap(), [path]);
+}
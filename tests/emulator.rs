@@ -0,0 +1,111 @@
+//! The emulator executes Hack machine code like the CPU of the nand2tetris hardware
+
+use vm_translator::hack::assembler::assemble;
+use vm_translator::hack::emulator::{Emulator, StopReason};
+
+fn emulator(asm: &str) -> Emulator {
+    Emulator::new(assemble(asm).unwrap().rom)
+}
+
+#[test]
+fn adds_two_numbers_in_ram() {
+    let mut emulator = emulator("@R0\nD=M\n@R1\nD=D+M\n@R2\nM=D\n");
+    emulator.poke(0, 1200);
+    emulator.poke(1, -34);
+
+    assert_eq!(emulator.run(100, None), StopReason::EndOfProgram);
+    assert_eq!(emulator.peek(2), 1166);
+    assert_eq!(emulator.cycles(), 6);
+}
+
+#[test]
+fn arithmetic_wraps_around() {
+    let mut emulator = emulator("@32767\nD=A\nD=D+1\n@R0\nM=D\nD=-D\n@R1\nM=D\n");
+    emulator.run(100, None);
+
+    assert_eq!(emulator.peek(0), -32768);
+    assert_eq!(emulator.peek(1), -32768);
+}
+
+#[test]
+fn every_computation_matches_the_alu() {
+    let (d, a, m): (i16, i16, i16) = (12, 5, -7);
+    let cases: [(&str, i16); 28] = [
+        ("0", 0), ("1", 1), ("-1", -1), ("D", d), ("A", a), ("!D", !d), ("!A", !a),
+        ("-D", -d), ("-A", -a), ("D+1", d + 1), ("A+1", a + 1), ("D-1", d - 1), ("A-1", a - 1),
+        ("D+A", d + a), ("D-A", d - a), ("A-D", a - d), ("D&A", d & a), ("D|A", d | a),
+        ("M", m), ("!M", !m), ("-M", -m), ("M+1", m + 1), ("M-1", m - 1), ("D+M", d + m),
+        ("D-M", d - m), ("M-D", m - d), ("D&M", d & m), ("D|M", d | m),
+    ];
+
+    for (comp, expected) in cases {
+        let mut emulator = emulator(&format!("@{}\nD=A\n@{}\nD={}\n", d, a, comp));
+        emulator.poke(a as u16, m);
+        emulator.run(100, None);
+
+        assert_eq!(emulator.d(), expected, "D={}", comp);
+    }
+}
+
+#[test]
+fn m_is_written_at_the_address_before_a_changes() {
+    let mut emulator = emulator("@100\nAM=A+1\n");
+    emulator.run(100, None);
+
+    assert_eq!(emulator.peek(100), 101);
+    assert_eq!(emulator.a(), 101);
+}
+
+#[test]
+fn jumps_follow_the_sign_of_the_result() {
+    let jumps = [
+        ("JGT", [false, false, true]),
+        ("JEQ", [false, true, false]),
+        ("JGE", [false, true, true]),
+        ("JLT", [true, false, false]),
+        ("JNE", [true, false, true]),
+        ("JLE", [true, true, false]),
+        ("JMP", [true, true, true]),
+    ];
+
+    for (jump, taken) in jumps {
+        for (value, taken) in [-1, 0, 1].into_iter().zip(taken) {
+            let mut emulator = emulator(&format!("@100\nD=A\n@R0\nM=D\n@JUMPED\nD={};{}\n@R0\nM=0\n(JUMPED)\n", value, jump));
+            emulator.run(100, None);
+
+            assert_eq!(emulator.peek(0) == 100, taken, "{} with {}", jump, value);
+        }
+    }
+}
+
+#[test]
+fn loop_to_itself_halts() {
+    let mut emulator = emulator("@R0\nM=1\n(END)\n@END\n0;JMP\n");
+
+    assert_eq!(emulator.run(1000, None), StopReason::Halted);
+    assert_eq!(emulator.peek(0), 1);
+    assert_eq!(emulator.pc(), 2);
+}
+
+#[test]
+fn stops_at_halt_address_or_cycle_limit() {
+    let mut emulator = emulator("(LOOP)\n@R0\nM=M+1\n@LOOP\n0;JMP\n");
+    assert_eq!(emulator.run(10, None), StopReason::CycleLimit);
+    assert_eq!(emulator.cycles(), 10);
+
+    emulator.reset();
+    assert_eq!(emulator.run(100, Some(2)), StopReason::Halted);
+    assert_eq!(emulator.pc(), 2);
+}
+
+#[test]
+fn jump_goes_to_the_a_from_before_the_instruction_writes_it() {
+    // the jump lands on `@R0`, not on the address 9 that A is set to
+    let mut emulator = emulator("@4\nAM=M-1;JMP\n@R1\nM=1\n@R0\nM=1\n");
+    emulator.poke(4, 10);
+    emulator.run(100, None);
+
+    assert_eq!(emulator.peek(4), 9);
+    assert_eq!(emulator.peek(0), 1);
+    assert_eq!(emulator.peek(1), 0);
+}
@@ -0,0 +1,9 @@
+//! The assembly in the emitters' macros is checked when they are expanded, so a typo fails the
+//! build with an error naming the line. So does a placeholder without an argument, an argument
+//! without a placeholder, or a placeholder where no value can go
+
+#[test]
+fn invalid_assembly_fails_to_compile() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
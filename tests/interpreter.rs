@@ -0,0 +1,180 @@
+//! The reference interpreter executes VM commands with the memory layout of the emitters
+
+use vm_translator::transformer::CommandDetails;
+use vm_translator::vm::interpreter::{Interpreter, StopReason, VmError};
+use vm_translator::vm::VmFile;
+use vm_translator::Source;
+
+fn load(files: &[(&str, &str)]) -> Result<Interpreter, VmError> {
+    let files: Vec<VmFile> = files.iter()
+        .map(|(name, text)| VmFile::from_source(&Source::new(*name, *text), &mut Vec::new()).unwrap())
+        .collect();
+
+    Interpreter::new(&files)
+}
+
+// run a single file with the stack set up, as in the project 7 tests
+fn run(text: &str) -> (Interpreter, StopReason) {
+    let mut vm = load(&[("Main.vm", text)]).unwrap();
+    vm.poke(0, 256);
+    let reason = vm.run(1000).unwrap();
+
+    (vm, reason)
+}
+
+// the items on the stack, bottom first
+fn stack(vm: &Interpreter) -> Vec<i16> {
+    vm.ram()[256..vm.peek(0) as usize].to_vec()
+}
+
+#[test]
+fn arithmetic_and_logic() {
+    let (vm, reason) = run("\
+        push constant 7\npush constant 8\nadd\n\
+        push constant 7\npush constant 8\nsub\n\
+        push constant 5\nneg\n\
+        push constant 12\npush constant 10\nand\n\
+        push constant 12\npush constant 10\nor\n\
+        push constant 0\nnot\n");
+
+    assert_eq!(reason, StopReason::EndOfProgram);
+    assert_eq!(stack(&vm), vec![15, -1, -5, 8, 14, -1]);
+}
+
+#[test]
+fn comparisons_push_true_as_minus_one() {
+    let (vm, _) = run("\
+        push constant 3\npush constant 3\neq\n\
+        push constant 3\npush constant 4\neq\n\
+        push constant 4\npush constant 3\ngt\n\
+        push constant 3\npush constant 4\ngt\n\
+        push constant 3\npush constant 4\nlt\n\
+        push constant 4\npush constant 3\nlt\n");
+
+    assert_eq!(stack(&vm), vec![-1, 0, -1, 0, -1, 0]);
+}
+
+#[test]
+fn arithmetic_wraps_around() {
+    let (vm, _) = run("push constant 32767\npush constant 1\nadd\npush constant 0\npush constant 32767\nsub\npush constant 2\nsub\n");
+
+    assert_eq!(stack(&vm), vec![-32768, 32767]);
+}
+
+#[test]
+fn forward_goto_skips_commands() {
+    let (vm, reason) = run("function Main.main 0\npush constant 1\ngoto SKIP\npush constant 2\nlabel SKIP\npush constant 3\n");
+
+    assert_eq!(reason, StopReason::EndOfProgram);
+    assert_eq!(stack(&vm), vec![1, 3]);
+}
+
+#[test]
+fn if_goto_jumps_on_any_non_zero_value() {
+    let source = "\
+        function Main.main 0\n\
+        push constant 0\npop temp 0\n\
+        push constant 5\npop temp 1\n\
+        label LOOP\n\
+        push temp 0\npush temp 1\nadd\npop temp 0\n\
+        push temp 1\npush constant 1\nsub\npop temp 1\n\
+        push temp 1\nif-goto LOOP\n";
+    let (vm, reason) = run(source);
+
+    assert_eq!(reason, StopReason::EndOfProgram);
+    assert_eq!(vm.peek(5), 15);
+    assert!(stack(&vm).is_empty());
+}
+
+#[test]
+fn jump_to_itself_halts() {
+    let (vm, reason) = run("function Main.main 0\npush constant 1\nlabel END\ngoto END\n");
+
+    assert_eq!(reason, StopReason::Halted);
+    assert_eq!(stack(&vm), vec![1]);
+}
+
+#[test]
+fn call_and_return_through_bootstrap() {
+    let main = "function Main.double 1\npush argument 0\npush argument 0\nadd\npop local 0\npush local 0\nreturn\n";
+    let sys = "function Sys.init 0\npush constant 21\ncall Main.double 1\npop static 0\nlabel END\ngoto END\n";
+    let mut vm = load(&[("Main.vm", main), ("Sys.vm", sys)]).unwrap();
+    vm.bootstrap().unwrap();
+
+    assert_eq!(vm.run(1000).unwrap(), StopReason::Halted);
+    assert_eq!(vm.statics()["Sys.0"], 42);
+    // the caller's frame is restored, with only Sys.init's call frame left below the stack
+    assert_eq!(vm.peek(0), 261);
+    assert_eq!(vm.peek(1), 261);
+}
+
+#[test]
+fn statics_belong_to_their_file() {
+    let a = "function A.set 0\npush constant 1\npop static 0\npush constant 0\nreturn\n";
+    let b = "function B.set 0\npush constant 2\npop static 0\npush constant 0\nreturn\n";
+    let sys = "function Sys.init 0\ncall A.set 0\ncall B.set 0\nlabel END\ngoto END\n";
+    let mut vm = load(&[("A.vm", a), ("B.vm", b), ("Sys.vm", sys)]).unwrap();
+    vm.bootstrap().unwrap();
+    vm.run(1000).unwrap();
+
+    assert_eq!(vm.statics()["A.0"], 1);
+    assert_eq!(vm.statics()["B.0"], 2);
+}
+
+#[test]
+fn popping_an_empty_stack_is_a_runtime_error() {
+    let mut vm = load(&[("Main.vm", "push constant 1\nadd\n")]).unwrap();
+    vm.poke(0, 256);
+
+    match vm.run(10) {
+        Err(VmError::Runtime { pc, message }) => {
+            assert_eq!(pc, 1);
+            assert_eq!(message, "stack underflow");
+        }
+        _ => panic!("expected a stack underflow"),
+    }
+}
+
+#[test]
+fn call_to_undefined_function_is_a_runtime_error() {
+    let mut vm = load(&[("Main.vm", "function Main.f 0\ncall Main.g 0\nreturn\n")]).unwrap();
+    vm.poke(0, 256);
+
+    let error = vm.run(10).unwrap_err();
+    assert!(error.to_string().contains("call to undefined function 'Main.g'"), "{}", error);
+}
+
+#[test]
+fn jump_to_undeclared_label_fails_to_load() {
+    // the parser rejects this program, so it is built from commands
+    let file = VmFile {
+        name: "Main".to_string(),
+        commands: vec![
+            (CommandDetails::Function { n_vars: 0, symbol: "Main.f".to_string() }, String::new()),
+            (CommandDetails::Goto("NOWHERE".to_string()), String::new()),
+        ],
+    };
+    let error = Interpreter::new(&[file]).err().unwrap();
+
+    assert!(error.to_string().contains("label 'Main.f$NOWHERE' is not declared"), "{}", error);
+}
+
+#[test]
+fn statics_are_listed_in_order() {
+    let (vm, _) = run("push constant 1\npop static 2\npush constant 2\npop static 0\npush constant 3\npop static 1\n");
+
+    let symbols: Vec<&str> = vm.statics().keys().map(String::as_str).collect();
+    assert_eq!(symbols, ["Main.0", "Main.1", "Main.2"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn file_name_that_is_not_unicode_is_an_error() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::path::Path::new(std::ffi::OsStr::from_bytes(b"Ma\xffin.vm"));
+    match VmFile::parse(path) {
+        Err(vm_translator::TransformError::IoError(message)) => assert!(message.contains("not valid unicode"), "{}", message),
+        other => panic!("{:?}", other.map(|file| file.name)),
+    }
+}
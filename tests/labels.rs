@@ -0,0 +1,120 @@
+//! Labels are scoped to their function, and misuse of them is reported once with its location
+
+use vm_translator::{translate, translate_to, Options, Source};
+use vm_translator::hack::assembler::assemble;
+use vm_translator::hack::emulator::Emulator;
+use vm_translator::transformer::emit::HALT_LABEL;
+
+mod common;
+
+// the errors of translating a single file, rendered
+fn errors(source: &str) -> Vec<String> {
+    common::errors(translate(&[Source::new("Main.vm", source)], &Options::default()))
+}
+
+#[test]
+fn label_named_like_a_return_label_is_rejected() {
+    let errors = errors("function Main.f 0\nlabel ret.1\npush constant 1\nreturn\n");
+
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("label 'ret.1' is reserved"), "{}", errors[0]);
+    assert!(errors[0].contains("Main.vm:2:7"), "{}", errors[0]);
+}
+
+#[test]
+fn label_ending_like_a_return_label_is_rejected() {
+    let errors = errors("function Main.f 0\nlabel g$ret.12\nreturn\n");
+
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("reserved"), "{}", errors[0]);
+}
+
+#[test]
+fn label_starting_like_a_return_label_is_accepted() {
+    let source = "function Main.f 0\nlabel ret.x\nlabel return\ngoto ret.x\nreturn\n";
+    assert!(errors(source).is_empty());
+}
+
+#[test]
+fn label_outside_function_is_reported_once() {
+    let errors = errors("label X\ngoto X\nfunction Main.f 0\nreturn\n");
+
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].contains("label 'X' is used outside of any function"), "{}", errors[0]);
+    assert!(errors[0].contains("Main.vm:1:7"), "{}", errors[0]);
+}
+
+#[test]
+fn jump_to_undeclared_label_is_reported_once_at_first_jump() {
+    let errors = errors("function Main.f 0\npush constant 0\nif-goto L\ngoto L\nreturn\nfunction Main.g 0\nlabel L\nreturn\n");
+
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].contains("label 'L' is not declared in function 'Main.f'"), "{}", errors[0]);
+    assert!(errors[0].contains("Main.vm:3:9"), "{}", errors[0]);
+}
+
+#[test]
+fn label_declared_twice_is_rejected() {
+    let errors = errors("function Main.f 0\nlabel L\nlabel L\nreturn\n");
+
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("declared more than once in function 'Main.f'"), "{}", errors[0]);
+    assert!(errors[0].contains("Main.vm:3:7"), "{}", errors[0]);
+}
+
+#[test]
+fn same_label_in_two_functions_is_allowed() {
+    let source = "function Main.f 0\nlabel L\ngoto L\nfunction Main.g 0\nlabel L\ngoto L\n";
+    let asm = translate(&[Source::new("Main.vm", source)], &Options::default()).unwrap();
+
+    assert!(asm.contains("(Main.f$L)"));
+    assert!(asm.contains("(Main.g$L)"));
+}
+
+#[test]
+fn function_named_like_a_prelude_subroutine_is_rejected() {
+    for name in ["$$call", "$$return", "$$eq", "$$gt", "$$lt", "neg_proc", "_L_DEADLOOP"] {
+        let errors = errors(&format!("function {} 0\nreturn\nfunction Main.f 0\ncall {} 0\nreturn\n", name, name));
+
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[0].contains(&format!("function name '{}' is reserved", name)), "{}", errors[0]);
+        assert!(errors[0].contains("Main.vm:1:10"), "{}", errors[0]);
+        assert!(errors[1].contains("Main.vm:4:6"), "{}", errors[1]);
+    }
+}
+
+#[test]
+fn label_in_a_file_without_functions_is_a_warning() {
+    let sources = [Source::new("Main.vm", "label X\ngoto X\nlabel Y\n")];
+    let report = translate_to(&sources, &Options::default(), Vec::new()).unwrap();
+
+    assert_eq!(report.warnings.len(), 2, "{:?}", report.warnings);
+    let warning = report.warnings[0].to_string();
+    assert!(warning.contains("label 'X' is used outside of any function"), "{}", warning);
+    assert!(warning.contains("Main.vm:1:7"), "{}", warning);
+}
+
+#[test]
+fn jump_to_undeclared_label_in_a_file_without_functions_is_rejected() {
+    let errors = errors("label X\ngoto Y\n");
+
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(errors[0].contains("label 'Y' is not declared in the file"), "{}", errors[0]);
+}
+
+#[test]
+fn basic_loop_translates_and_runs() {
+    let source = std::fs::read_to_string("tests/fixtures/BasicLoop.vm").unwrap();
+    let asm = translate(&[Source::new("BasicLoop.vm", source)], &Options::default()).unwrap();
+    let program = assemble(&asm).unwrap();
+
+    // the RAM the course's BasicLoop.tst sets up
+    let mut emulator = Emulator::new(program.rom);
+    for (address, value) in [(0, 256), (1, 300), (2, 400), (400, 3)] {
+        emulator.poke(address, value);
+    }
+    emulator.run(10_000, program.symbols.get(HALT_LABEL).copied());
+
+    assert_eq!(emulator.peek(0), 257);
+    assert_eq!(emulator.peek(256), 6);
+}
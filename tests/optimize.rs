@@ -0,0 +1,151 @@
+//! The optimizer rewrites commands into fewer ones that leave the same result
+
+use vm_translator::transformer::{ArithmeticType, CommandDetails, Segment};
+use vm_translator::vm::optimize::{optimize, OptLevel};
+
+fn push(value: i16) -> CommandDetails {
+    CommandDetails::Push(Segment::Constant, value)
+}
+
+fn arithmetic(op: ArithmeticType) -> CommandDetails {
+    CommandDetails::Arithmetic(op)
+}
+
+// optimize commands that have no source lines
+fn optimized(commands: &[CommandDetails], level: OptLevel) -> Vec<CommandDetails> {
+    let mut commands = commands.iter()
+        .map(|command| (command.clone(), String::new()))
+        .collect();
+    optimize(&mut commands, level);
+
+    commands.into_iter().map(|(command, _)| command).collect()
+}
+
+#[test]
+fn comparison_is_folded_when_subtracting_does_not_overflow() {
+    let commands = [push(-5), push(7), arithmetic(ArithmeticType::Gt)];
+    assert_eq!(optimized(&commands, OptLevel::Basic), vec![push(0)]);
+
+    let commands = [push(-5), push(7), arithmetic(ArithmeticType::Lt)];
+    assert_eq!(optimized(&commands, OptLevel::Basic), vec![push(-1)]);
+}
+
+#[test]
+fn comparison_is_not_folded_when_subtracting_overflows() {
+    for (x, y) in [(-32768, 1), (30000, -30000), (-32768, 0), (0, -32768)] {
+        for op in [ArithmeticType::Gt, ArithmeticType::Lt] {
+            let commands = [push(x), push(y), arithmetic(op)];
+            assert_eq!(optimized(&commands, OptLevel::Full), commands.to_vec(), "{} {:?} {}", x, op, y);
+        }
+    }
+}
+
+#[test]
+fn equality_is_folded_even_when_subtracting_overflows() {
+    let commands = [push(-32768), push(1), arithmetic(ArithmeticType::Eq)];
+    assert_eq!(optimized(&commands, OptLevel::Basic), vec![push(0)]);
+}
+
+#[test]
+fn binary_arithmetic_on_constants_is_folded() {
+    let cases = [
+        (ArithmeticType::Add, 7, 8, 15),
+        (ArithmeticType::Add, 32767, 1, -32768),
+        (ArithmeticType::Sub, 7, 8, -1),
+        (ArithmeticType::Eq, 7, 7, -1),
+        (ArithmeticType::Eq, 7, 8, 0),
+        (ArithmeticType::And, 12, 10, 8),
+        (ArithmeticType::Or, 12, 10, 14),
+    ];
+
+    for (op, x, y, result) in cases {
+        let commands = [push(x), push(y), arithmetic(op)];
+        assert_eq!(optimized(&commands, OptLevel::Basic), vec![push(result)], "{} {:?} {}", x, op, y);
+    }
+}
+
+#[test]
+fn unary_arithmetic_on_a_constant_is_folded() {
+    assert_eq!(optimized(&[push(5), arithmetic(ArithmeticType::Neg)], OptLevel::Basic), vec![push(-5)]);
+    assert_eq!(optimized(&[push(0), arithmetic(ArithmeticType::Not)], OptLevel::Basic), vec![push(-1)]);
+}
+
+#[test]
+fn folding_repeats_until_nothing_changes() {
+    let mut commands = [push(2), push(3), arithmetic(ArithmeticType::Add), push(4), arithmetic(ArithmeticType::Sub)]
+        .into_iter()
+        .map(|command| (command, String::new()))
+        .collect();
+
+    assert_eq!(optimize(&mut commands, OptLevel::Basic), 2);
+    assert_eq!(commands.len(), 1);
+    assert_eq!(commands[0].0, push(1));
+}
+
+#[test]
+fn folded_command_keeps_the_source_of_what_it_replaced() {
+    let mut commands = vec![
+        (push(2), "push constant 2".to_string()),
+        (push(3), "push constant 3 ".to_string()),
+        (arithmetic(ArithmeticType::Add), "add".to_string()),
+    ];
+    optimize(&mut commands, OptLevel::Basic);
+
+    assert_eq!(commands, vec![(push(5), "push constant 2; push constant 3; add".to_string())]);
+}
+
+#[test]
+fn push_then_pop_to_the_same_place_is_removed() {
+    let local = CommandDetails::Push(Segment::Local, 1);
+    let commands = [local.clone(), CommandDetails::Pop(Segment::Local, 1)];
+    assert!(optimized(&commands, OptLevel::Basic).is_empty());
+
+    let commands = [local, CommandDetails::Pop(Segment::Local, 2)];
+    assert_eq!(optimized(&commands, OptLevel::Basic), commands.to_vec());
+}
+
+#[test]
+fn adding_or_subtracting_zero_is_removed() {
+    let local = CommandDetails::Push(Segment::Local, 0);
+    for op in [ArithmeticType::Add, ArithmeticType::Sub] {
+        let commands = [local.clone(), push(0), arithmetic(op)];
+        assert_eq!(optimized(&commands, OptLevel::Basic), vec![local.clone()]);
+    }
+}
+
+#[test]
+fn nothing_is_rewritten_without_optimization() {
+    let commands = [push(2), push(3), arithmetic(ArithmeticType::Add), arithmetic(ArithmeticType::Not)];
+    assert_eq!(optimized(&commands, OptLevel::None), commands.to_vec());
+}
+
+#[test]
+fn adding_a_constant_becomes_add_const_at_full() {
+    let local = CommandDetails::Push(Segment::Local, 0);
+    let commands = [local.clone(), push(3), arithmetic(ArithmeticType::Add)];
+    assert_eq!(optimized(&commands, OptLevel::Basic), commands.to_vec());
+    assert_eq!(optimized(&commands, OptLevel::Full), vec![local.clone(), CommandDetails::AddConst(3)]);
+
+    let commands = [local.clone(), push(3), arithmetic(ArithmeticType::Sub)];
+    assert_eq!(optimized(&commands, OptLevel::Full), vec![local, CommandDetails::AddConst(-3)]);
+}
+
+#[test]
+fn add_consts_are_merged_and_folded() {
+    let local = CommandDetails::Push(Segment::Local, 0);
+    let commands = [local.clone(), CommandDetails::AddConst(1), CommandDetails::AddConst(2)];
+    assert_eq!(optimized(&commands, OptLevel::Full), vec![local.clone(), CommandDetails::AddConst(3)]);
+
+    let commands = [local.clone(), CommandDetails::AddConst(1), CommandDetails::AddConst(-1)];
+    assert_eq!(optimized(&commands, OptLevel::Full), vec![local]);
+
+    let commands = [push(5), CommandDetails::AddConst(3)];
+    assert_eq!(optimized(&commands, OptLevel::Full), vec![push(8)]);
+}
+
+#[test]
+fn not_before_if_goto_becomes_if_not_goto_at_full() {
+    let commands = [arithmetic(ArithmeticType::Not), CommandDetails::IfGoto("L".to_string())];
+    assert_eq!(optimized(&commands, OptLevel::Basic), commands.to_vec());
+    assert_eq!(optimized(&commands, OptLevel::Full), vec![CommandDetails::IfNotGoto("L".to_string())]);
+}
@@ -0,0 +1,64 @@
+//! The peephole pass leaves out instructions that have no effect, one rule at a time
+
+use vm_translator::hack::buffer::Line;
+use vm_translator::hack::instruction::Instruction;
+use vm_translator::hack::peephole::optimize;
+
+// the lines of a program that the pass keeps, one per line
+fn kept(asm: &str) -> String {
+    let lines: Vec<Line> = asm.lines()
+        .map(|code| Line { instruction: Instruction::parse(code).unwrap().unwrap(), note: None })
+        .collect();
+    let keep = optimize(&lines);
+
+    asm.lines()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(code, _)| code)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn push_then_pop_of_the_stack_pointer_cancels() {
+    assert_eq!(kept("@SP\nM=M+1\n@SP\nM=M-1\nD=M"), "@SP\nD=M");
+    assert_eq!(kept("@SP\nM=M-1\n@SP\nM=M+1\nD=M"), "@SP\nD=M");
+    assert_eq!(kept("@SP\nM=M+1\n@LCL\nM=M-1"), "@SP\nM=M+1\n@LCL\nM=M-1");
+}
+
+#[test]
+fn load_of_what_a_already_holds_is_removed() {
+    assert_eq!(kept("@SP\nD=M\n@SP\nM=D"), "@SP\nD=M\nM=D");
+    assert_eq!(kept("@SP\nA=M\n@SP\nM=D"), "@SP\nA=M\n@SP\nM=D");
+}
+
+#[test]
+fn write_to_d_that_is_overwritten_before_it_is_read_is_removed() {
+    assert_eq!(kept("D=A\n@5\nD=A\n@R13\nM=D"), "@5\nD=A\n@R13\nM=D");
+    assert_eq!(kept("D=A\n@5\nD=D+A"), "D=A\n@5\nD=D+A");
+}
+
+#[test]
+fn write_to_d_before_a_jump_is_kept() {
+    assert_eq!(kept("D=A\n@END\n0;JMP\nD=M"), "D=A\n@END\n0;JMP\nD=M");
+}
+
+#[test]
+fn jump_to_the_next_instruction_is_removed() {
+    assert_eq!(kept("D=A\n@NEXT\n0;JMP\n(NEXT)\nM=D"), "D=A\n(NEXT)\nM=D");
+    assert_eq!(kept("@NEXT\nD;JEQ\n(OTHER)\n(NEXT)\nM=D"), "(OTHER)\n(NEXT)\nM=D");
+    assert_eq!(kept("@OTHER\n0;JMP\n(NEXT)\nM=D"), "@OTHER\n0;JMP\n(NEXT)\nM=D");
+}
+
+#[test]
+fn jump_after_a_label_is_kept() {
+    // code jumping to OTHER reaches the jump with another A
+    assert_eq!(kept("@NEXT\n(OTHER)\n0;JMP\n(NEXT)\nM=D"), "@NEXT\n(OTHER)\n0;JMP\n(NEXT)\nM=D");
+}
+
+#[test]
+fn rules_do_not_look_across_a_label() {
+    assert_eq!(kept("@SP\nM=M+1\n(L)\n@SP\nM=M-1"), "@SP\nM=M+1\n(L)\n@SP\nM=M-1");
+    assert_eq!(kept("@SP\nD=M\n(L)\n@SP\nM=D"), "@SP\nD=M\n(L)\n@SP\nM=D");
+    assert_eq!(kept("D=A\n(L)\nD=M"), "D=A\n(L)\nD=M");
+}
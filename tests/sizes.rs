@@ -0,0 +1,113 @@
+//! The ROM budget and how the size table attributes instructions to files and functions
+
+use vm_translator::{translate_to, Bootstrap, Options, Report, Severity, Source, TransformError, TransformResult};
+
+fn translate(files: &[(&str, &str)], options: &Options) -> TransformResult<Report> {
+    let sources: Vec<Source> = files.iter().map(|(name, text)| Source::new(*name, *text)).collect();
+    translate_to(&sources, options, Vec::new())
+}
+
+/// `pushes` times `push constant 1` followed by `negs` times `neg`, outside of any function
+fn straight_line(pushes: usize, negs: usize) -> String {
+    "push constant 1\n".repeat(pushes) + &"neg\n".repeat(negs)
+}
+
+#[test]
+fn the_default_budget_is_the_whole_rom() {
+    assert_eq!(Options::default().rom_budget, 32768);
+}
+
+#[test]
+fn a_program_filling_the_rom_exactly_fits_the_default_budget() {
+    // 5458 * 6 + 4 * 5 words
+    let source = straight_line(5458, 4);
+    let report = translate(&[("Main.vm", &source)], &Options::default()).unwrap();
+
+    assert_eq!(report.instructions, 32768);
+    assert_eq!(report.sizes.total(), 32768);
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+}
+
+#[test]
+fn a_program_one_command_over_the_rom_fails_the_default_budget() {
+    let source = straight_line(5458, 5);
+
+    match translate(&[("Main.vm", &source)], &Options::default()) {
+        Err(TransformError::RomBudgetExceeded { budget, sizes }) => {
+            assert_eq!(budget, 32768);
+            assert_eq!(sizes.total(), 32773);
+        }
+        other => panic!("expected the budget to be exceeded, got {:?}", other.map(|r| r.instructions)),
+    }
+}
+
+#[test]
+fn the_budget_is_inclusive() {
+    let source = straight_line(3, 0);
+    let size = translate(&[("Main.vm", &source)], &Options::default()).unwrap().instructions;
+
+    let exact = Options { rom_budget: size, ..Options::default() };
+    assert!(translate(&[("Main.vm", &source)], &exact).is_ok());
+
+    let short = Options { rom_budget: size - 1, ..Options::default() };
+    let error = translate(&[("Main.vm", &source)], &short).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!("error: the program needs {} words of ROM, more than the budget of {}", size, size - 1)
+    );
+}
+
+#[test]
+fn going_over_the_budget_can_be_only_a_warning() {
+    let source = straight_line(3, 0);
+    let options = Options { rom_budget: 10, over_budget: Severity::Warning, ..Options::default() };
+    let report = translate(&[("Main.vm", &source)], &options).unwrap();
+
+    assert_eq!(report.instructions, 18);
+    assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+    assert_eq!(report.warnings[0].severity, Severity::Warning);
+    assert_eq!(report.warnings[0].message, "the program needs 18 words of ROM, more than the budget of 10");
+}
+
+#[test]
+fn instructions_are_attributed_to_the_function_declared_before_them() {
+    let main = "push constant 1\n\
+                function Main.small 0\npush constant 1\nreturn\n\
+                function Main.large 0\npush constant 1\npush constant 1\npush constant 1\nreturn\n";
+    let options = Options { keep_unreachable: true, ..Options::default() };
+    let report = translate(&[("Main.vm", main)], &options).unwrap();
+
+    assert_eq!(report.sizes.files.len(), 1);
+    let file = &report.sizes.files[0];
+    assert_eq!(file.name, "Main");
+
+    // largest first, and the two extra pushes are the only difference between them
+    let names: Vec<&str> = file.functions.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["Main.large", "Main.small"]);
+    assert_eq!(file.functions[0].instructions - file.functions[1].instructions, 12);
+
+    // the push before the first function counts for the file, but for no function
+    let in_functions: usize = file.functions.iter().map(|f| f.instructions).sum();
+    assert_eq!(file.instructions - in_functions, 6);
+}
+
+#[test]
+fn files_are_sized_separately_and_add_up_to_the_program() {
+    let sys = "function Sys.init 0\ncall Main.main 0\nlabel END\ngoto END\n";
+    let main = "function Main.main 0\npush constant 1\npush constant 1\nadd\nreturn\n";
+    let options = Options { bootstrap: Bootstrap::Always, ..Options::default() };
+    let report = translate(&[("Sys.vm", sys), ("Main.vm", main)], &options).unwrap();
+    let sizes = &report.sizes;
+
+    let names: Vec<&str> = sizes.files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["Main", "Sys"]);
+    assert!(sizes.files[0].instructions > sizes.files[1].instructions);
+
+    for file in sizes.files.iter() {
+        assert_eq!(file.functions.len(), 1);
+        assert_eq!(file.functions[0].instructions, file.instructions);
+    }
+
+    assert!(sizes.overhead > 0);
+    assert_eq!(sizes.total(), report.instructions);
+}
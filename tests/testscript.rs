@@ -0,0 +1,116 @@
+//! Test scripts are parsed, run on the emulator and their output compared like the CPU emulator does
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use vm_translator::hack::assembler::{assemble, AssembledProgram};
+use vm_translator::testscript::script::{parse, Command, OutputColumn, Variable};
+use vm_translator::testscript::{compare, run_script};
+
+// adds 2 and 3 into RAM[0] in six instructions
+const ADD: &str = "@2\nD=A\n@3\nD=D+A\n@0\nM=D\n";
+
+const SCRIPT: &str = "\
+// adds two numbers
+load Add.asm,
+output-file Add.out,
+compare-to Add.cmp,
+output-list RAM[0]%D2.6.2 D%X1.4.1 A%B1.16.1 time;
+
+set RAM[0] 0,
+repeat 6 {
+    ticktock;
+}
+output;
+echo \"done\";
+";
+
+// a fresh folder holding the script and the file to compare with
+fn script_dir(name: &str, cmp: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("vm_translator_testscript_{}", std::process::id()))
+        .join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("Add.tst"), SCRIPT).unwrap();
+    fs::write(dir.join("Add.cmp"), cmp).unwrap();
+
+    dir
+}
+
+fn load(path: &Path) -> Result<AssembledProgram, String> {
+    assert_eq!(path.file_name().unwrap(), "Add.asm");
+    assemble(ADD).map_err(|e| e.to_string())
+}
+
+const OUTPUT: &str = "\
+|  RAM[0]  |  D   |        A         |  time  |
+|       5  | 0005 | 0000000000000000 |      6 |
+";
+
+#[test]
+fn script_is_parsed_into_commands() {
+    let commands = parse(SCRIPT).unwrap();
+
+    let column = |variable, name: &str, format, pad_left, len, pad_right| OutputColumn {
+        variable,
+        name: name.to_string(),
+        format,
+        pad_left,
+        len,
+        pad_right,
+    };
+    assert_eq!(commands, vec![
+        Command::Load("Add.asm".to_string()),
+        Command::OutputFile("Add.out".to_string()),
+        Command::CompareTo("Add.cmp".to_string()),
+        Command::OutputList(vec![
+            column(Variable::Ram(0), "RAM[0]", 'D', 2, 6, 2),
+            column(Variable::D, "D", 'X', 1, 4, 1),
+            column(Variable::A, "A", 'B', 1, 16, 1),
+            column(Variable::Time, "time", 'D', 1, 6, 1),
+        ]),
+        Command::Set(Variable::Ram(0), 0),
+        Command::Repeat(6, vec![Command::TickTock]),
+        Command::Output,
+        Command::Echo("done".to_string()),
+    ]);
+}
+
+#[test]
+fn invalid_scripts_are_rejected() {
+    assert!(parse("repeat 2 { ticktock;").is_err());
+    assert!(parse("set X 1;").is_err());
+    assert!(parse("output-list RAM[0]%Q1.2.1;").is_err());
+    assert!(parse("echo \"unterminated;").is_err());
+    assert!(parse("jump 1;").is_err());
+}
+
+#[test]
+fn output_matching_the_comparison_passes() {
+    let dir = script_dir("Pass", OUTPUT);
+    let result = run_script(&dir.join("Add.tst"), &load).unwrap();
+
+    assert!(result.passed());
+    assert_eq!(result.compare_path, Some(dir.join("Add.cmp")));
+    assert_eq!(result.echoes, ["done"]);
+    assert_eq!(fs::read_to_string(dir.join("Add.out")).unwrap(), OUTPUT);
+}
+
+#[test]
+fn output_differing_from_the_comparison_fails_at_the_line() {
+    let dir = script_dir("Fail", &OUTPUT.replace("|       5  |", "|       6  |"));
+    let result = run_script(&dir.join("Add.tst"), &load).unwrap();
+
+    let mismatch = result.mismatch.unwrap();
+    assert_eq!(mismatch.line, 2);
+    assert_eq!(mismatch.expected, "|       6  | 0005 | 0000000000000000 |      6 |");
+    assert_eq!(mismatch.actual, "|       5  | 0005 | 0000000000000000 |      6 |");
+}
+
+#[test]
+fn comparison_ignores_padding_and_matches_stars() {
+    assert!(compare("|   5 |  12 |\n", "| 5| ***|\n").is_none());
+    assert!(compare("|   5 |\n", "|   5 |\n|   6 |\n").is_some());
+    assert!(compare("|   5 |  12 |\n", "|   5 |\n").is_some());
+}
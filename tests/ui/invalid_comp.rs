@@ -0,0 +1,15 @@
+use hack_macro::emit_hack;
+
+struct Emitter;
+
+impl Emitter {
+    fn decrement_twice(&mut self) {
+        emit_hack! {r"
+            @SP
+            A=M-1
+            M=M-2       // there is no M-2
+        "};
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use hack_macro::emit_fmt_hack;
+
+struct Emitter;
+
+impl Emitter {
+    fn jump_if_zero(&mut self, label: &str) {
+        emit_fmt_hack!(r"
+            @{label}
+            D;JMPP
+        ");
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use hack_macro::emit_fmt_hack;
+
+struct Emitter;
+
+impl Emitter {
+    fn store(&mut self, dest: &str) {
+        emit_fmt_hack!(r"
+            @SP
+            {dest}=M
+        ");
+    }
+}
+
+fn main() {}
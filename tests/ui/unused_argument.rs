@@ -0,0 +1,14 @@
+use hack_macro::emit_fmt_hack;
+
+struct Emitter;
+
+impl Emitter {
+    fn load(&mut self, address: i16, offset: i16) {
+        emit_fmt_hack!(r"
+            @{0}
+            D=M
+        ", address, offset);
+    }
+}
+
+fn main() {}